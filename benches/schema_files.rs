@@ -0,0 +1,115 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use git_repository::objs::tree::EntryMode;
+use git_repository::traverse::tree::Recorder;
+use postgit::SchemaRepository;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+const OTHER_DIRS: usize = 200;
+const FILES_PER_DIR: usize = 250;
+
+fn git(repo_path: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(repo_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Creates a repository with a small schema among a large number of unrelated files
+fn setup_monorepo(repo_path: &Path) {
+    git(repo_path, &["init", "--quiet"]);
+    git(repo_path, &["config", "user.email", "test@example.com"]);
+    git(repo_path, &["config", "user.name", "test"]);
+
+    for dir in 0..OTHER_DIRS {
+        let dir_path = repo_path.join(format!("services/service_{dir}/src"));
+        fs::create_dir_all(&dir_path).unwrap();
+        for file in 0..FILES_PER_DIR {
+            fs::write(
+                dir_path.join(format!("file_{file}.rs")),
+                format!("// {dir} {file}"),
+            )
+            .unwrap();
+        }
+    }
+
+    let schema_path = repo_path.join("db/schema");
+    fs::create_dir_all(&schema_path).unwrap();
+    fs::write(schema_path.join("schema.sql"), "create schema app;").unwrap();
+    for table in 0..20 {
+        fs::write(
+            schema_path.join(format!("table_{table}.sql")),
+            format!("-- import db/schema/schema.sql\ncreate table app.table_{table} (id int);"),
+        )
+        .unwrap();
+    }
+
+    git(repo_path, &["add", "."]);
+    git(repo_path, &["commit", "--quiet", "-m", "monorepo"]);
+}
+
+/// Reads the schema files by traversing the whole commit tree, then filtering by path
+fn full_tree_schema_files(repo_path: &Path, schema_path: &Path) -> Vec<Vec<u8>> {
+    let repo = git_repository::open(repo_path).unwrap();
+    let tree = repo
+        .rev_parse_single("HEAD")
+        .unwrap()
+        .object()
+        .unwrap()
+        .try_into_commit()
+        .unwrap()
+        .tree()
+        .unwrap();
+
+    let mut recorder = Recorder::default();
+    tree.traverse()
+        .breadthfirst::<Recorder>(&mut recorder)
+        .unwrap();
+
+    recorder
+        .records
+        .iter()
+        .filter(|entry| {
+            matches!(entry.mode, EntryMode::Blob)
+                && entry
+                    .filepath
+                    .to_string()
+                    .starts_with(schema_path.to_str().unwrap())
+        })
+        .map(|entry| repo.find_object(entry.oid).unwrap().data.clone())
+        .collect()
+}
+
+fn schema_files(c: &mut Criterion) {
+    let dir = tempdir().unwrap();
+    setup_monorepo(dir.path());
+    let repo_path = dir.path().to_str().unwrap();
+
+    let mut group = c.benchmark_group("schema_files");
+    group.sample_size(10);
+
+    group.bench_function("full tree traversal", |b| {
+        b.iter(|| full_tree_schema_files(dir.path(), Path::new("db/schema")))
+    });
+    group.bench_function("subtree traversal", |b| {
+        b.iter(|| {
+            SchemaRepository::open(repo_path)
+                .unwrap()
+                .schema_files("HEAD", "db/schema")
+                .unwrap()
+        })
+    });
+    group.bench_function("subtree traversal, shared repository", |b| {
+        let repo = SchemaRepository::open(repo_path).unwrap();
+        b.iter(|| repo.schema_files("HEAD", "db/schema").unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, schema_files);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -6,6 +6,10 @@ use clap::{Args, Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Name of a target database defined in a `[targets.<TARGET>]` section of postgit.toml
+    #[arg(long, global = true)]
+    pub target: Option<String>,
 }
 
 #[derive(Args)]
@@ -14,12 +18,14 @@ pub struct DiffArgs {
     #[arg(long, short, default_value = ".")]
     pub repo_path: String,
 
-    /// Git revision where the source schema can be found.
-    /// This may be omitted for the first migration, when the database is empty
+    /// Git revision where the source schema can be found. `A...B` selects the merge base of A and B.
+    /// `auto` uses the commit of the last deployment on the target database, which is the default of push and plan,
+    /// and `empty` an empty schema, which is the default of the other commands
     #[arg(long, short)]
     pub from: Option<String>,
 
     /// Git revision where the target schema can be found.
+    /// A `A..B` range diffs B against A, and `A...B` diffs B against the merge base of A and B
     #[arg(long, short)]
     pub to: String,
 
@@ -31,6 +37,174 @@ pub struct DiffArgs {
     pub path: String,
 }
 
+#[derive(Args, Default)]
+pub struct PushOptions {
+    /// Pushes without checking whether the target database matches the source schema
+    #[arg(long, conflicts_with = "drift_report_only")]
+    pub ignore_drift: bool,
+
+    /// Reports the differences between the target database and the source schema, without aborting the push
+    #[arg(long)]
+    pub drift_report_only: bool,
+
+    /// Runs the migration statement by statement in a transaction, reports the results and rolls it back
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Applies changes which may lose data, such as dropping tables or columns
+    #[arg(long)]
+    pub allow_destructive: bool,
+
+    /// Refuses to push if a statement rewrites a table larger than this size, e.g. `500MB`
+    #[arg(long, value_name = "SIZE", value_parser = crate::impact::parse_size)]
+    pub max_rewrite_size: Option<u64>,
+
+    #[command(flatten)]
+    pub lock: LockOptions,
+}
+
+#[derive(Args, Default)]
+pub struct LockOptions {
+    /// Waits for other pushes of the schema to the target database to finish, up to the lock_timeout setting
+    #[arg(long, overrides_with = "no_wait")]
+    pub wait: bool,
+
+    /// Fails if another push of the schema to the target database is in progress (default)
+    #[arg(long, overrides_with = "wait")]
+    pub no_wait: bool,
+}
+
+#[derive(Args)]
+pub struct PushArgs {
+    #[command(flatten)]
+    pub diff: DiffArgs,
+
+    #[command(flatten)]
+    pub options: PushOptions,
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub diff: DiffArgs,
+
+    /// File the plan is written to
+    #[arg(long, short)]
+    pub out: String,
+
+    /// Plans without checking whether the target database matches the source schema
+    #[arg(long, conflicts_with = "drift_report_only")]
+    pub ignore_drift: bool,
+
+    /// Reports the differences between the target database and the source schema, without aborting the plan
+    #[arg(long)]
+    pub drift_report_only: bool,
+}
+
+#[derive(Args)]
+pub struct ApplyArgs {
+    /// Plan file written by the plan command
+    pub plan: String,
+
+    /// Applies changes which may lose data, such as dropping tables or columns
+    #[arg(long)]
+    pub allow_destructive: bool,
+
+    #[command(flatten)]
+    pub lock: LockOptions,
+}
+
+#[derive(Args)]
+pub struct SchemaArgs {
+    /// Path to the root of the git repository
+    #[arg(long, short, default_value = ".")]
+    pub repo_path: String,
+
+    /// Git revision where the schema can be found. The working tree is used if omitted
+    #[arg(long = "ref")]
+    pub git_ref: Option<String>,
+
+    /// Path to the schema file or directory, relative to the repo root
+    pub path: String,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+#[derive(Args)]
+pub struct GraphArgs {
+    #[command(flatten)]
+    pub schema: SchemaArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "dot")]
+    pub format: GraphFormat,
+
+    /// Number the files in the order they are loaded
+    #[arg(long)]
+    pub order: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StatusFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    pub schema: SchemaArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "text")]
+    pub format: StatusFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LintFormat {
+    Text,
+    Sarif,
+}
+
+#[derive(Args)]
+pub struct LintArgs {
+    #[command(flatten)]
+    pub diff: DiffArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "text")]
+    pub format: LintFormat,
+}
+
+#[derive(Args)]
+pub struct RollbackArgs {
+    /// Path to the root of the git repository
+    #[arg(long, short, default_value = ".")]
+    pub repo_path: String,
+
+    /// Path to the schema file or directory, relative to the repo root
+    pub path: String,
+
+    #[command(flatten)]
+    pub options: PushOptions,
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// Maximum number of deployments to show
+    #[arg(long, default_value_t = 20)]
+    pub limit: i64,
+
+    /// Shows the details and script of a deployment
+    #[arg(long, value_name = "ID")]
+    pub show: Option<i64>,
+}
+
 #[derive(Args)]
 pub struct WatchArgs {
     /// Path to the directory to watch
@@ -42,7 +216,23 @@ pub enum Commands {
     /// Shows the migration diff between two schemas
     Diff(DiffArgs),
     /// Calculates the migration diff between two schemas and applies it to the target database
-    Push(DiffArgs),
+    Push(PushArgs),
+    /// Writes the migration diff between two schemas to a plan file, to be reviewed before applying it
+    Plan(PlanArgs),
+    /// Applies a plan file to the target database, if its schema did not change since the plan was created
+    Apply(ApplyArgs),
+    /// Reverts the last deployment of the target database, using the down migration recorded with it
+    Rollback(RollbackArgs),
     /// Watches a directory and applies the migrations to the target database
     Watch(WatchArgs),
+    /// Reports missing or redundant import comments, based on the objects each file defines and references
+    LintImports(SchemaArgs),
+    /// Reports the statements of the migration diff between two schemas which take heavy locks or rewrite tables
+    Lint(LintArgs),
+    /// Prints the dependency graph of the schema files
+    Graph(GraphArgs),
+    /// Lists the deployments recorded in the target database
+    History(HistoryArgs),
+    /// Compares the target database with a schema, exiting with a non-zero status if they differ
+    Status(StatusArgs),
 }
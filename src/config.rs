@@ -1,9 +1,13 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
+
+use crate::manifest::Manifest;
 
 #[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct PostgresConfig {
@@ -48,6 +52,11 @@ impl PostgresConfig {
         config
     }
 
+    /// Identifies the database, regardless of the user connecting to it
+    pub fn database_id(&self) -> String {
+        format!("{}:{}/{}", self.host, self.port, self.dbname)
+    }
+
     pub fn to_url(&self) -> String {
         let mut url = "postgresql://".to_owned();
 
@@ -90,6 +99,147 @@ impl Default for WatchConfig {
     }
 }
 
+/// What to do when the target database still differs from the pushed schema after a push
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyMode {
+    Off,
+    #[default]
+    Warn,
+    Fail,
+}
+
+/// How the statements of a migration are grouped in transactions.
+/// Statements which cannot run in a transaction, e.g. `create index concurrently`, always run on their own.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionMode {
+    /// Consecutive statements run in the same transaction
+    #[default]
+    Single,
+    /// Each statement runs in its own transaction
+    PerStatement,
+    /// Statements run one by one without transaction control, e.g. for scripts with their own `begin` and `commit`
+    None,
+}
+
+/// Retries of the statements which fail to acquire their locks within `lock_timeout`
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+pub struct RetryConfig {
+    /// Number of times a statement is run before giving up, 1 disabling retries
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Delay before the first retry, doubled after each retry
+    #[serde(default = "default_delay_ms")]
+    pub delay_ms: u64,
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+fn default_attempts() -> u32 {
+    1
+}
+
+fn default_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_delay_ms() -> u64 {
+    30000
+}
+
+impl RetryConfig {
+    /// Delay before running a statement again after the given failed attempt, starting at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .delay_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)));
+        Duration::from_millis(delay.min(self.max_delay_ms))
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: default_attempts(),
+            delay_ms: default_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct PushConfig {
+    #[serde(default)]
+    pub verify: VerifyMode,
+    #[serde(default)]
+    pub transaction_mode: TransactionMode,
+    /// Patterns of the objects which destructive changes are allowed on, e.g. `app.legacy_*`
+    #[serde(default)]
+    pub allow_destructive: Vec<String>,
+    /// Session settings of the connections to the target database, e.g. `5s`
+    pub lock_timeout: Option<String>,
+    pub statement_timeout: Option<String>,
+    pub idle_in_transaction_session_timeout: Option<String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl PushConfig {
+    /// Command-line options setting the timeouts when connecting to the target database
+    pub fn session_options(&self) -> Option<String> {
+        let settings = [
+            ("lock_timeout", &self.lock_timeout),
+            ("statement_timeout", &self.statement_timeout),
+            (
+                "idle_in_transaction_session_timeout",
+                &self.idle_in_transaction_session_timeout,
+            ),
+        ];
+        let options: Vec<String> = settings
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .as_ref()
+                    .map(|value| format!("-c {}={}", name, value.replace(' ', "\\ ")))
+            })
+            .collect();
+        if options.is_empty() {
+            None
+        } else {
+            Some(options.join(" "))
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Off,
+    Note,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Name of the severity, which is also its SARIF level
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Off => "none",
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct LintConfig {
+    /// Severities overriding the default ones, by rule id
+    #[serde(default)]
+    pub rules: HashMap<String, Severity>,
+}
+
 #[derive(Deserialize, PartialEq, Eq, Debug, Default)]
 pub struct DiffEngineConfig {
     pub command: Option<String>,
@@ -99,6 +249,46 @@ pub struct DiffEngineConfig {
     pub target: PostgresConfig,
 }
 
+/// A target database defined in a `[targets.<name>]` section, which can be selected with the `--target` option
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+pub struct NamedTargetConfig {
+    #[serde(flatten)]
+    pub connection: PostgresConfig,
+    /// Variables overriding the ones defined in the `[vars]` section
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+pub struct SchemaConfig {
+    /// Load order used when the schema root does not contain a manifest file
+    #[serde(flatten)]
+    pub manifest: Manifest,
+    /// Whether to order the files based on the objects they define and reference, in addition to imports
+    #[serde(default = "default_infer_dependencies")]
+    pub infer_dependencies: bool,
+}
+
+fn default_infer_dependencies() -> bool {
+    true
+}
+
+impl Default for SchemaConfig {
+    fn default() -> Self {
+        SchemaConfig {
+            manifest: Manifest::default(),
+            infer_dependencies: default_infer_dependencies(),
+        }
+    }
+}
+
+/// A repository schema files can be imported from, defined in a `[repositories.<name>]` section
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct RepositoryConfig {
+    /// Path to a local clone of the repository
+    pub path: String,
+}
+
 #[derive(Deserialize, PartialEq, Eq, Debug)]
 pub struct Config {
     #[serde(default)]
@@ -106,7 +296,22 @@ pub struct Config {
     #[serde(default)]
     pub target: PostgresConfig,
     #[serde(default)]
+    pub targets: HashMap<String, NamedTargetConfig>,
+    /// Name of the target selected with `use_target`
+    #[serde(skip)]
+    pub target_name: Option<String>,
+    #[serde(default)]
+    pub schema: SchemaConfig,
+    #[serde(default)]
+    pub repositories: HashMap<String, RepositoryConfig>,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    #[serde(default)]
     pub watch: WatchConfig,
+    #[serde(default)]
+    pub push: PushConfig,
+    #[serde(default)]
+    pub lint: LintConfig,
 }
 
 impl Config {
@@ -131,6 +336,28 @@ impl Config {
 
         Ok(config)
     }
+
+    /// Connection parameters of the target database, with the session timeouts of the `[push]` section
+    pub fn target_session_config(&self) -> tokio_postgres::Config {
+        let mut config = self.target.to_tokio_postgres_config();
+        if let Some(options) = self.push.session_options() {
+            config.options(&options);
+        }
+        config
+    }
+
+    /// Uses the connection parameters and variables of one of the `[targets.<name>]` sections
+    pub fn use_target(&mut self, name: &str) -> Result<()> {
+        match self.targets.remove(name) {
+            Some(target) => {
+                self.target = target.connection;
+                self.target_name = Some(name.to_string());
+                self.vars.extend(target.vars);
+                Ok(())
+            }
+            None => bail!("Target {} is not defined in postgit.toml", name),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -168,9 +395,16 @@ mod tests {
                     host: "localhost".to_string(),
                     port: 5432
                 },
+                targets: HashMap::new(),
+                target_name: None,
+                schema: SchemaConfig::default(),
+                repositories: HashMap::new(),
+                vars: HashMap::new(),
                 watch: WatchConfig {
                     recreate_db_on_fail: true
-                }
+                },
+                push: PushConfig::default(),
+                lint: LintConfig::default(),
             },
             config
         );
@@ -209,6 +443,19 @@ mod tests {
 
         [watch]
         recreate_db_on_fail=false
+
+        [push]
+        verify='fail'
+        transaction_mode='per_statement'
+        allow_destructive=['app.legacy_*']
+        lock_timeout='5s'
+        statement_timeout='1 min'
+
+        [push.retry]
+        attempts=3
+
+        [lint.rules]
+        create-index-non-concurrently='error'
         "#,
         )
         .unwrap();
@@ -238,11 +485,80 @@ mod tests {
                     host: "target_host".to_string(),
                     port: 3214
                 },
+                targets: HashMap::new(),
+                target_name: None,
+                schema: SchemaConfig::default(),
+                repositories: HashMap::new(),
+                vars: HashMap::new(),
                 watch: WatchConfig {
                     recreate_db_on_fail: false
-                }
+                },
+                push: PushConfig {
+                    verify: VerifyMode::Fail,
+                    transaction_mode: TransactionMode::PerStatement,
+                    allow_destructive: vec!["app.legacy_*".to_string()],
+                    lock_timeout: Some("5s".to_string()),
+                    statement_timeout: Some("1 min".to_string()),
+                    idle_in_transaction_session_timeout: None,
+                    retry: RetryConfig {
+                        attempts: 3,
+                        ..RetryConfig::default()
+                    },
+                },
+                lint: LintConfig {
+                    rules: HashMap::from([(
+                        "create-index-non-concurrently".to_string(),
+                        Severity::Error
+                    )])
+                },
             },
             config
         );
+        assert_eq!(
+            Some("-c lock_timeout=5s -c statement_timeout=1\\ min".to_string()),
+            config.push.session_options()
+        );
+        assert_eq!(
+            vec![1000, 2000, 4000],
+            (1..=3)
+                .map(|attempt| config.push.retry.delay(attempt).as_millis())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(30000, config.push.retry.delay(10).as_millis());
+    }
+
+    #[test]
+    fn it_uses_named_targets() {
+        let mut config: Config = toml::from_str(
+            r#"
+        [vars]
+        role='app_user'
+        tablespace='pg_default'
+
+        [targets.prod]
+        dbname='prod_db'
+        host='prod_host'
+
+        [targets.prod.vars]
+        tablespace='fast_ssd'
+        "#,
+        )
+        .unwrap();
+
+        config.use_target("prod").unwrap();
+
+        assert_eq!(
+            "postgresql://postgres@prod_host:5432/prod_db",
+            config.target.to_url()
+        );
+        assert_eq!("prod_host:5432/prod_db", config.target.database_id());
+        assert_eq!(
+            HashMap::from([
+                ("role".to_string(), "app_user".to_string()),
+                ("tablespace".to_string(), "fast_ssd".to_string())
+            ]),
+            config.vars
+        );
+        assert!(config.use_target("staging").is_err());
     }
 }
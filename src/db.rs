@@ -1,5 +1,14 @@
-use anyhow::Result;
-use tokio_postgres::NoTls;
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{Client, NoTls, SimpleQueryMessage, Transaction};
+
+use crate::config::{PushConfig, RetryConfig, TransactionMode};
+use crate::lexer::{split_statements, strip_leading_comments};
 
 #[tokio::main]
 pub async fn create_db(config: &tokio_postgres::Config) -> Result<()> {
@@ -55,10 +64,971 @@ pub async fn run_sql_script(script: &str, config: &tokio_postgres::Config) -> Re
         }
     });
 
-    // Now we can execute a simple statement that just returns its parameter.
+    run_statements(&mut client, script, &PushConfig::default(), None)
+        .await?
+        .commit()
+        .await?;
+
+    Ok(())
+}
+
+/// A database error raised by one of the statements of a script
+#[derive(Debug)]
+pub struct StatementError {
+    /// Byte offset of the statement in the script
+    pub offset: usize,
+    pub error: tokio_postgres::Error,
+    /// Number of times the statement was run
+    pub attempts: u32,
+    /// Number of statements of the script committed before the error
+    pub committed: usize,
+}
+
+impl StatementError {
+    /// Whether the statement failed to acquire its locks within `lock_timeout`
+    pub fn is_lock_timeout(&self) -> bool {
+        self.error.code() == Some(&SqlState::LOCK_NOT_AVAILABLE)
+    }
+
+    /// Explains that retries were exhausted, if they were
+    pub fn retries_note(&self) -> Option<String> {
+        if self.is_lock_timeout() && self.attempts > 1 {
+            Some(format!(
+                "Gave up after {} attempts to acquire the locks",
+                self.attempts
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Notes about retries and about the statements committed before the error
+    pub fn notes(&self) -> Option<String> {
+        let notes: Vec<String> = self
+            .retries_note()
+            .into_iter()
+            .chain(committed_note(self.committed))
+            .collect();
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.join("\n"))
+        }
+    }
+}
+
+fn committed_note(committed: usize) -> Option<String> {
+    if committed > 0 {
+        Some(format!(
+            "{} statement(s) of the script were committed before the error",
+            committed
+        ))
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for StatementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error.as_db_error() {
+            Some(db_error) => write!(f, "{}: {}", db_error.severity(), db_error.message())?,
+            None => self.error.fmt(f)?,
+        }
+        if let Some(notes) = self.notes() {
+            write!(f, "\n{}", notes)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for StatementError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.error)
+    }
+}
+
+/// Statements which run in the same transaction, or one by one outside of any transaction
+struct Batch<'a> {
+    /// Statements with their byte offset in the script
+    statements: Vec<(usize, &'a str)>,
+    transactional: bool,
+}
+
+fn batches(script: &str, mode: TransactionMode, server_version: i32) -> Vec<Batch<'_>> {
+    let mut batches: Vec<Batch> = vec![];
+    for statement in split_statements(script) {
+        let offset = statement.as_ptr() as usize - script.as_ptr() as usize;
+        let transactional =
+            mode != TransactionMode::None && runs_in_transaction(statement, server_version);
+        match batches.last_mut() {
+            // in single mode, consecutive transactional statements share a transaction
+            Some(batch)
+                if batch.transactional == transactional
+                    && (mode == TransactionMode::Single || !transactional) =>
+            {
+                batch.statements.push((offset, statement))
+            }
+            _ => batches.push(Batch {
+                statements: vec![(offset, statement)],
+                transactional,
+            }),
+        }
+    }
+    batches
+}
+
+/// Runs the statements of the script according to the transaction mode, retrying the ones which fail to acquire their locks.
+/// Returns the uncommitted transaction of the last statements if they run in one, or a new transaction otherwise.
+/// The deployment, if any, is recorded with the first committed statements and finished in the returned transaction.
+async fn run_statements<'c>(
+    client: &'c mut Client,
+    script: &str,
+    push: &PushConfig,
+    deployment: Option<&Deployment<'_>>,
+) -> Result<Transaction<'c>> {
+    let server_version = server_version(client).await?;
+    let batches = batches(script, push.transaction_mode, server_version);
+    let mut progress = Progress {
+        deployment,
+        id: None,
+        committed: 0,
+    };
+
+    let (last, rest) = match batches.split_last() {
+        Some((last, rest)) => (Some(last), rest),
+        None => (None, &[][..]),
+    };
+    for batch in rest {
+        execute_and_commit(client, batch, push, &mut progress).await?;
+    }
+
+    let transaction = match last {
+        Some(last) if last.transactional => {
+            let mut transaction = client.transaction().await?;
+            progress.start(&transaction).await?;
+            execute_in_transaction(&mut transaction, last, &push.retry)
+                .await
+                .map_err(|e| progress.describe(e))?;
+            transaction
+        }
+        Some(last) => {
+            execute_and_commit(client, last, push, &mut progress).await?;
+            client.transaction().await?
+        }
+        None => client.transaction().await?,
+    };
+    let total = batches.iter().map(|batch| batch.statements.len()).sum();
+    progress.finish(&transaction, total).await?;
+    Ok(transaction)
+}
+
+/// Runs the batch and commits it, along with the progress of the deployment
+async fn execute_and_commit(
+    client: &mut Client,
+    batch: &Batch<'_>,
+    push: &PushConfig,
+    progress: &mut Progress<'_>,
+) -> Result<()> {
+    if batch.transactional {
+        let mut transaction = client.transaction().await?;
+        progress.start(&transaction).await?;
+        execute_in_transaction(&mut transaction, batch, &push.retry)
+            .await
+            .map_err(|e| progress.describe(e))?;
+        progress
+            .advance(&transaction, batch.statements.len())
+            .await?;
+        transaction.commit().await?;
+    } else {
+        for statement in &batch.statements {
+            execute_statement(client, *statement, &push.retry)
+                .await
+                .map_err(|e| progress.describe(e))?;
+            // record the deployment once a statement committed, so failures which changed nothing leave no row
+            progress.start(&*client).await?;
+            progress.advance(&*client, 1).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Tracks the statements committed by a deployment, so that a failed deployment is recorded as unfinished
+struct Progress<'a> {
+    deployment: Option<&'a Deployment<'a>>,
+    /// Id of the deployment record, once inserted
+    id: Option<i64>,
+    committed: usize,
+}
+
+impl Progress<'_> {
+    /// Records the deployment as unfinished, if it is not recorded yet
+    async fn start(&mut self, client: &impl tokio_postgres::GenericClient) -> Result<()> {
+        let deployment = match (self.deployment, self.id) {
+            (Some(deployment), None) => deployment,
+            _ => return Ok(()),
+        };
+        client.batch_execute(DEPLOYMENTS_TABLE).await?;
+        let row = client
+            .query_one(
+                r#"
+insert into postgit.deployments
+  (from_commit, to_commit, schema_path, script_hash, script, down_script, engine, deployed_by, started_at, committed_statements)
+values
+  ($1, $2, $3, encode(sha256(convert_to($4, 'UTF8')), 'hex'), $4, $8, $5, coalesce($6, current_user), $7, 0)
+returning id
+"#,
+                &[
+                    &deployment.from_commit,
+                    &deployment.to_commit,
+                    &deployment.schema_path,
+                    &deployment.script,
+                    &deployment.engine,
+                    &deployment.deployed_by,
+                    &deployment.started_at,
+                    &deployment.down_script,
+                ],
+            )
+            .await?;
+        self.id = Some(row.get(0));
+        Ok(())
+    }
+
+    async fn advance(
+        &mut self,
+        client: &impl tokio_postgres::GenericClient,
+        statements: usize,
+    ) -> Result<()> {
+        self.committed += statements;
+        if let Some(id) = self.id {
+            client
+                .execute(
+                    "update postgit.deployments set committed_statements = $2 where id = $1",
+                    &[&id, &(self.committed as i32)],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(
+        &mut self,
+        client: &impl tokio_postgres::GenericClient,
+        total: usize,
+    ) -> Result<()> {
+        self.start(client).await?;
+        if let Some(id) = self.id {
+            client
+                .execute(
+                    "update postgit.deployments set committed_statements = $2, finished_at = clock_timestamp() where id = $1",
+                    &[&id, &(total as i32)],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Adds the number of statements committed before the error to it
+    fn describe(&self, err: anyhow::Error) -> anyhow::Error {
+        match err.downcast::<StatementError>() {
+            Ok(mut e) => {
+                e.committed = self.committed;
+                e.into()
+            }
+            Err(err) => match committed_note(self.committed) {
+                Some(note) => anyhow!("{}\n{}", err, note),
+                None => err,
+            },
+        }
+    }
+}
+
+/// Runs the batch in a savepoint, which is rolled back before running the whole batch again on lock timeouts
+async fn execute_in_transaction(
+    transaction: &mut Transaction<'_>,
+    batch: &Batch<'_>,
+    retry: &RetryConfig,
+) -> Result<()> {
+    if retry.attempts <= 1 {
+        return Ok(execute_batch(transaction, batch, 1).await?);
+    }
+
+    let mut attempt = 1;
+    loop {
+        let savepoint = transaction.savepoint("postgit_retry").await?;
+        match execute_batch(&savepoint, batch, attempt).await {
+            Ok(()) => return Ok(savepoint.commit().await?),
+            Err(e) if e.is_lock_timeout() && attempt < retry.attempts => {
+                savepoint.rollback().await?;
+                wait_before_retry(&e, batch, retry).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Runs a statement outside of any transaction, running it again on lock timeouts
+async fn execute_statement(
+    client: &Client,
+    statement: (usize, &str),
+    retry: &RetryConfig,
+) -> Result<()> {
+    let single = Batch {
+        statements: vec![statement],
+        transactional: false,
+    };
+    let mut attempt = 1;
+    loop {
+        match execute_batch(client, &single, attempt).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.is_lock_timeout() && attempt < retry.attempts => {
+                wait_before_retry(&e, &single, retry).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+async fn wait_before_retry(error: &StatementError, batch: &Batch<'_>, retry: &RetryConfig) {
+    let delay = retry.delay(error.attempts);
+    let statement = batch
+        .statements
+        .iter()
+        .find(|(offset, _)| *offset == error.offset)
+        .map_or("", |(_, statement)| strip_leading_comments(statement));
+    eprintln!(
+        "warning: lock timeout on attempt {}/{}, retrying in {:.1}s: {}",
+        error.attempts,
+        retry.attempts,
+        delay.as_secs_f64(),
+        statement.lines().next().unwrap_or_default()
+    );
+    tokio::time::sleep(delay).await;
+}
+
+async fn execute_batch(
+    client: &impl tokio_postgres::GenericClient,
+    batch: &Batch<'_>,
+    attempt: u32,
+) -> Result<(), StatementError> {
+    for (offset, statement) in &batch.statements {
+        client
+            .batch_execute(statement)
+            .await
+            .map_err(|error| StatementError {
+                offset: *offset,
+                error,
+                attempts: attempt,
+                committed: 0,
+            })?;
+    }
+    Ok(())
+}
+
+/// Returns the `server_version_num` of the server, e.g. 140005
+async fn server_version(client: &Client) -> Result<i32> {
+    Ok(client
+        .query_one("select current_setting('server_version_num')::int", &[])
+        .await?
+        .get(0))
+}
+
+/// Whether PostgreSQL allows running the statement inside a transaction block
+pub fn runs_in_transaction(statement: &str, server_version: i32) -> bool {
+    let non_transactional = Regex::new(
+        r"(?is)^(create\s+(unique\s+)?index\s+concurrently|drop\s+index\s+concurrently|reindex\s.*\bconcurrently\b|vacuum\b|alter\s+system\b|(create|drop)\s+(database|tablespace)\b|alter\s+database\s.*\bset\s+tablespace\b|(create|alter)\s+subscription\b|drop\s+subscription\b)",
+    )
+    .unwrap();
+    // enum values can only be added in a transaction since PostgreSQL 12
+    let add_enum_value = Regex::new(r"(?is)^alter\s+type\s.*\badd\s+value\b").unwrap();
+
+    let statement = strip_leading_comments(statement);
+    !non_transactional.is_match(statement)
+        && (server_version >= 120000 || !add_enum_value.is_match(statement))
+}
+
+/// Outcome of a statement of a dry run
+#[derive(Debug, PartialEq, Eq)]
+pub enum DryRunOutcome {
+    /// Number of rows the statement affected
+    Applied(u64),
+    Failed(String),
+    /// The statement cannot run inside a transaction, so it was not run
+    NotTransactional,
+    /// A previous statement failed
+    NotRun,
+}
+
+#[derive(Debug)]
+pub struct DryRunStatement {
+    pub statement: String,
+    pub outcome: DryRunOutcome,
+    pub duration: Duration,
+}
+
+/// Runs the statements one by one in a transaction which is rolled back
+#[tokio::main]
+pub async fn dry_run(
+    statements: &[&str],
+    config: &tokio_postgres::Config,
+) -> Result<Vec<DryRunStatement>> {
+    let (mut client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    let server_version = server_version(&client).await?;
     let transaction = client.transaction().await?;
-    transaction.batch_execute(script).await?;
-    transaction.commit().await?;
+    let mut failed = false;
+    let mut results = vec![];
+    for statement in statements {
+        let start = Instant::now();
+        let outcome = if failed {
+            DryRunOutcome::NotRun
+        } else if !runs_in_transaction(statement, server_version) {
+            DryRunOutcome::NotTransactional
+        } else {
+            match transaction.simple_query(statement).await {
+                Ok(messages) => DryRunOutcome::Applied(
+                    messages
+                        .iter()
+                        .filter_map(|message| match message {
+                            SimpleQueryMessage::CommandComplete(rows) => Some(*rows),
+                            _ => None,
+                        })
+                        .sum(),
+                ),
+                Err(err) => {
+                    failed = true;
+                    DryRunOutcome::Failed(match err.as_db_error() {
+                        Some(db_error) => db_error.message().to_string(),
+                        None => err.to_string(),
+                    })
+                }
+            }
+        };
+        results.push(DryRunStatement {
+            statement: strip_leading_comments(statement).to_string(),
+            outcome,
+            duration: start.elapsed(),
+        });
+    }
+    transaction.rollback().await?;
+
+    Ok(results)
+}
+
+/// A migration pushed to the target database
+pub struct Deployment<'a> {
+    pub from_commit: Option<&'a str>,
+    pub to_commit: &'a str,
+    pub schema_path: &'a str,
+    pub script: &'a str,
+    /// Script reverting the migration, used by the `rollback` command
+    pub down_script: Option<&'a str>,
+    pub engine: &'a str,
+    /// Defaults to the database user
+    pub deployed_by: Option<&'a str>,
+    pub started_at: SystemTime,
+}
+
+/// A deployment recorded in the `postgit.deployments` table
+#[derive(Debug)]
+pub struct DeploymentRecord {
+    pub id: i64,
+    pub from_commit: Option<String>,
+    pub to_commit: String,
+    pub schema_path: String,
+    pub script_hash: String,
+    pub script: String,
+    pub down_script: Option<String>,
+    pub engine: String,
+    pub deployed_by: String,
+    pub started_at: String,
+    /// Not set when the deployment failed after committing some of its statements
+    pub finished_at: Option<String>,
+    /// Not set for deployments recorded by previous versions of PostGit
+    pub committed_statements: Option<i32>,
+}
+
+const DEPLOYMENTS_TABLE: &str = r#"
+create schema if not exists postgit;
+create table if not exists postgit.deployments (
+  id bigint primary key generated always as identity,
+  from_commit text,
+  to_commit text not null,
+  schema_path text not null,
+  script_hash text not null,
+  script text not null,
+  engine text not null,
+  deployed_by text not null,
+  started_at timestamptz not null,
+  finished_at timestamptz not null
+);
+alter table postgit.deployments add column if not exists down_script text;
+alter table postgit.deployments add column if not exists committed_statements integer;
+alter table postgit.deployments alter column finished_at drop not null;
+"#;
+
+/// Whether deployments were ever recorded in the database
+#[tokio::main]
+pub async fn has_deployments_table(config: &tokio_postgres::Config) -> Result<bool> {
+    let (client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    deployments_table_exists(&client).await
+}
+
+async fn deployments_table_exists(client: &Client) -> Result<bool> {
+    Ok(client
+        .query_one("select to_regclass('postgit.deployments') is not null", &[])
+        .await?
+        .get(0))
+}
+
+/// Creates the table deployments are recorded in, so that it is not reported as a difference by the diff engine
+pub fn create_deployments_table(config: &tokio_postgres::Config) -> Result<()> {
+    run_sql_script(DEPLOYMENTS_TABLE, config)
+}
+
+/// Runs the migration script and records the deployment. The deployment is recorded along with the first committed statements,
+/// so that a failure after some of them were committed leaves an unfinished deployment, and finished with the last ones.
+#[tokio::main]
+pub async fn deploy(
+    deployment: &Deployment<'_>,
+    push: &PushConfig,
+    config: &tokio_postgres::Config,
+) -> Result<()> {
+    let (mut client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    run_statements(&mut client, deployment.script, push, Some(deployment))
+        .await?
+        .commit()
+        .await?;
 
     Ok(())
 }
+
+/// Returns the most recent deployments first, or the deployment with the given id
+#[tokio::main]
+pub async fn get_deployments(
+    config: &tokio_postgres::Config,
+    limit: i64,
+    id: Option<i64>,
+) -> Result<Vec<DeploymentRecord>> {
+    query_deployments(config, limit, id, None).await
+}
+
+/// Returns the most recent deployment of the schema at the given path
+#[tokio::main]
+pub async fn last_deployment(
+    config: &tokio_postgres::Config,
+    schema_path: &str,
+) -> Result<Option<DeploymentRecord>> {
+    Ok(query_deployments(config, 1, None, Some(schema_path))
+        .await?
+        .pop())
+}
+
+async fn query_deployments(
+    config: &tokio_postgres::Config,
+    limit: i64,
+    id: Option<i64>,
+    schema_path: Option<&str>,
+) -> Result<Vec<DeploymentRecord>> {
+    let (client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    if !deployments_table_exists(&client).await? {
+        return Ok(vec![]);
+    }
+
+    let rows = client
+        .query(
+            r#"
+select id, from_commit, to_commit, schema_path, script_hash, script, down_script, engine, deployed_by,
+  to_char(started_at at time zone 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"'),
+  to_char(finished_at at time zone 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"'), committed_statements
+from postgit.deployments
+where ($2::bigint is null or id = $2) and ($3::text is null or schema_path = $3)
+order by id desc
+limit $1
+"#,
+            &[&limit, &id, &schema_path],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| DeploymentRecord {
+            id: row.get(0),
+            from_commit: row.get(1),
+            to_commit: row.get(2),
+            schema_path: row.get(3),
+            script_hash: row.get(4),
+            script: row.get(5),
+            down_script: row.get(6),
+            engine: row.get(7),
+            deployed_by: row.get(8),
+            started_at: row.get(9),
+            finished_at: row.get(10),
+            committed_statements: row.get(11),
+        })
+        .collect())
+}
+
+/// Returns a hash of the definitions of the objects of the database, outside of the postgit schema
+#[tokio::main]
+pub async fn schema_fingerprint(config: &tokio_postgres::Config) -> Result<String> {
+    let (client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    // qualifies all names in the definitions
+    client.batch_execute("set search_path to ''").await?;
+    let fingerprint: String = client
+        .query_one(
+            r#"
+with user_namespace as (
+  select oid, nspname, nspacl from pg_namespace
+  where nspname not like 'pg\_%' and nspname not in ('information_schema', 'postgit')
+),
+definition as (
+  select 'schema ' || nspname || ' ' || coalesce(nspacl::text, '') from user_namespace
+  union all
+  select 'relation ' || c.oid::regclass || ' ' || c.relkind::text || ' ' || coalesce(c.relacl::text, '')
+  from pg_class c where c.relnamespace in (select oid from user_namespace)
+  union all
+  select 'column ' || a.attrelid::regclass || '.' || quote_ident(a.attname) || ' '
+    || format_type(a.atttypid, a.atttypmod) || ' ' || a.attnotnull || ' '
+    || coalesce(pg_get_expr(d.adbin, d.adrelid), '') || ' ' || coalesce(a.attacl::text, '')
+  from pg_attribute a
+  join pg_class c on c.oid = a.attrelid
+  left join pg_attrdef d on d.adrelid = a.attrelid and d.adnum = a.attnum
+  where c.relnamespace in (select oid from user_namespace) and a.attnum > 0 and not a.attisdropped
+  union all
+  select 'constraint ' || coalesce(nullif(conrelid, 0)::regclass::text, contypid::regtype::text)
+    || ' ' || quote_ident(conname) || ' ' || pg_get_constraintdef(oid)
+  from pg_constraint where connamespace in (select oid from user_namespace)
+  union all
+  select 'index ' || pg_get_indexdef(i.indexrelid)
+  from pg_index i join pg_class c on c.oid = i.indexrelid
+  where c.relnamespace in (select oid from user_namespace)
+  union all
+  select 'view ' || c.oid::regclass || ' ' || pg_get_viewdef(c.oid)
+  from pg_class c where c.relkind in ('v', 'm') and c.relnamespace in (select oid from user_namespace)
+  union all
+  select 'function ' || case when p.prokind in ('f', 'p') then pg_get_functiondef(p.oid) else p.oid::regprocedure::text end
+    || ' ' || coalesce(p.proacl::text, '')
+  from pg_proc p where p.pronamespace in (select oid from user_namespace)
+  union all
+  select 'trigger ' || pg_get_triggerdef(t.oid)
+  from pg_trigger t join pg_class c on c.oid = t.tgrelid
+  where not t.tgisinternal and c.relnamespace in (select oid from user_namespace)
+  union all
+  select 'type ' || t.oid::regtype || ' ' || t.typtype::text || ' ' || coalesce(t.typacl::text, '')
+  from pg_type t
+  where t.typtype in ('d', 'e', 'r', 'm') and t.typnamespace in (select oid from user_namespace)
+  union all
+  select 'enum ' || e.enumtypid::regtype || ' ' || e.enumsortorder || ' ' || quote_literal(e.enumlabel)
+  from pg_enum e join pg_type t on t.oid = e.enumtypid
+  where t.typnamespace in (select oid from user_namespace)
+  union all
+  select 'policy ' || p.polrelid::regclass || ' ' || quote_ident(p.polname) || ' ' || p.polcmd::text || ' '
+    || p.polpermissive || ' ' || p.polroles::text || ' '
+    || coalesce(pg_get_expr(p.polqual, p.polrelid), '') || ' '
+    || coalesce(pg_get_expr(p.polwithcheck, p.polrelid), '')
+  from pg_policy p join pg_class c on c.oid = p.polrelid
+  where c.relnamespace in (select oid from user_namespace)
+  union all
+  select 'extension ' || quote_ident(extname) || ' ' || extversion from pg_extension
+)
+select encode(sha256(convert_to(coalesce(string_agg(d, E'\n' order by d), ''), 'UTF8')), 'hex')
+from definition as def(d)
+"#,
+            &[],
+        )
+        .await?
+        .get(0);
+
+    Ok(fingerprint)
+}
+
+/// Statistics of a table of the target database
+#[derive(Debug, PartialEq, Eq)]
+pub struct TableStats {
+    /// Estimated number of rows
+    pub rows: i64,
+    /// Size of the table in bytes, including TOAST but not indexes
+    pub size: u64,
+}
+
+/// Returns the statistics of the existing tables, by schema-qualified name
+#[tokio::main]
+pub async fn table_stats(
+    config: &tokio_postgres::Config,
+    tables: &[String],
+) -> Result<HashMap<String, TableStats>> {
+    let (client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    let rows = client
+        .query(
+            r#"
+select n.nspname || '.' || c.relname,
+  case when c.reltuples >= 0 then c.reltuples::bigint else coalesce(s.n_live_tup, 0) end,
+  pg_table_size(c.oid)
+from pg_class c
+join pg_namespace n on n.oid = c.relnamespace
+left join pg_stat_user_tables s on s.relid = c.oid
+where c.relkind in ('r', 'p', 'm') and n.nspname || '.' || c.relname = any($1)
+"#,
+            &[&tables],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let size: i64 = row.get(2);
+            (
+                row.get(0),
+                TableStats {
+                    rows: row.get(1),
+                    size: size as u64,
+                },
+            )
+        })
+        .collect())
+}
+
+/// Key of the advisory lock taken by pushes of a schema to a target
+pub fn push_lock_key(target: &str, schema_path: &str) -> i64 {
+    let digest = Sha256::digest(format!("postgit:{}:{}", target, schema_path).as_bytes());
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// A session holding the advisory lock which prevents concurrent pushes. The lock is released when it is dropped.
+pub struct PushLock {
+    _client: Client,
+    // dropping the runtime closes the session
+    _runtime: tokio::runtime::Runtime,
+}
+
+/// Takes the advisory lock, waiting for it if `wait` is set, up to `lock_timeout`
+pub fn lock_push(key: i64, wait: bool, config: &tokio_postgres::Config) -> Result<PushLock> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let client = runtime.block_on(acquire_push_lock(key, wait, config))?;
+    Ok(PushLock {
+        _client: client,
+        _runtime: runtime,
+    })
+}
+
+async fn acquire_push_lock(
+    key: i64,
+    wait: bool,
+    config: &tokio_postgres::Config,
+) -> Result<Client> {
+    let mut config = config.clone();
+    config.application_name("postgit");
+    let (client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    let acquired = if wait {
+        match client.execute("select pg_advisory_lock($1)", &[&key]).await {
+            Ok(_) => true,
+            Err(e) if e.code() == Some(&SqlState::LOCK_NOT_AVAILABLE) => false,
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        client
+            .query_one("select pg_try_advisory_lock($1)", &[&key])
+            .await?
+            .get(0)
+    };
+    if !acquired {
+        let holder = push_lock_holder(&client, key)
+            .await?
+            .unwrap_or_else(|| "a session which released it since".to_string());
+        bail!(
+            "Another push of the schema to the target database is in progress: the lock is held by {}.{}",
+            holder,
+            if wait {
+                " Gave up waiting for it after lock_timeout"
+            } else {
+                " Use --wait to wait for it"
+            }
+        );
+    }
+    Ok(client)
+}
+
+/// Describes the session holding the advisory lock
+async fn push_lock_holder(client: &Client, key: i64) -> Result<Option<String>> {
+    // bigint advisory lock keys are split in the classid and objid columns
+    let row = client
+        .query_opt(
+            r#"
+select a.pid, a.usename::text, a.application_name, coalesce(host(a.client_addr), 'local'), a.backend_start::text
+from pg_locks l
+join pg_stat_activity a on a.pid = l.pid
+where l.locktype = 'advisory' and l.granted and l.objsubid = 1
+  and l.classid::bigint = $1 and l.objid::bigint = $2
+"#,
+            &[&((key >> 32) & 0xffff_ffff), &(key & 0xffff_ffff)],
+        )
+        .await?;
+    Ok(row.map(|row| {
+        format!(
+            "pid {} (user {}, application {}, client {}, connected at {})",
+            row.get::<_, i32>(0),
+            row.get::<_, Option<String>>(1).unwrap_or_default(),
+            row.get::<_, String>(2),
+            row.get::<_, String>(3),
+            row.get::<_, String>(4)
+        )
+    }))
+}
+
+/// Whether the database contains no objects, apart from the ones created by extensions and the postgit schema
+#[tokio::main]
+pub async fn is_empty(config: &tokio_postgres::Config) -> Result<bool> {
+    let (client, connection) = config.connect(NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    let has_objects: bool = client
+        .query_one(
+            r#"
+with user_namespace as (
+  select oid, nspname from pg_namespace
+  where nspname not like 'pg\_%' and nspname not in ('information_schema', 'postgit')
+),
+extension_object as (
+  select objid from pg_depend where deptype = 'e'
+)
+select exists (
+  select from user_namespace n where n.nspname <> 'public'
+) or exists (
+  select from pg_class c
+  where c.relnamespace in (select oid from user_namespace)
+    and c.oid not in (select objid from extension_object)
+) or exists (
+  select from pg_proc p
+  where p.pronamespace in (select oid from user_namespace)
+    and p.oid not in (select objid from extension_object)
+) or exists (
+  select from pg_type t
+  where t.typnamespace in (select oid from user_namespace)
+    and t.typtype in ('d', 'e', 'r', 'm')
+    and t.oid not in (select objid from extension_object)
+)
+"#,
+            &[],
+        )
+        .await?
+        .get(0);
+
+    Ok(!has_objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_detects_statements_which_cannot_run_in_transactions() {
+        let pg14 = 140005;
+        assert!(runs_in_transaction("create index i on t (c);", pg14));
+        assert!(runs_in_transaction(
+            "alter table t add column concurrently int;",
+            pg14
+        ));
+        assert!(!runs_in_transaction(
+            "-- index\nCREATE UNIQUE INDEX CONCURRENTLY i ON t (c);",
+            pg14
+        ));
+        assert!(!runs_in_transaction("drop index concurrently i;", pg14));
+        assert!(!runs_in_transaction(
+            "reindex (verbose) table concurrently t;",
+            pg14
+        ));
+        assert!(!runs_in_transaction("vacuum analyze t;", pg14));
+        assert!(!runs_in_transaction(
+            "alter system set work_mem = '64MB';",
+            pg14
+        ));
+        assert!(runs_in_transaction(
+            "alter type app.mood add value 'happy';",
+            pg14
+        ));
+        assert!(!runs_in_transaction(
+            "alter type app.mood add value 'happy';",
+            110000
+        ));
+    }
+
+    #[test]
+    fn it_groups_statements_in_transactions() {
+        let script = "create table t (id int);\ninsert into t values (1);\ncreate index concurrently on t (id);\nvacuum t;\nalter table t add column c int;";
+        let groups = |mode| {
+            batches(script, mode, 140005)
+                .iter()
+                .map(|batch| (batch.statements.len(), batch.transactional))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![(2, true), (2, false), (1, true)],
+            groups(TransactionMode::Single)
+        );
+        assert_eq!(
+            vec![(1, true), (1, true), (2, false), (1, true)],
+            groups(TransactionMode::PerStatement)
+        );
+        assert_eq!(vec![(5, false)], groups(TransactionMode::None));
+        assert_eq!(
+            (24, "\ninsert into t values (1);"),
+            batches(script, TransactionMode::Single, 140005)[0].statements[1]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use sqlparser::ast::{
+    visit_expressions, visit_relations, ArrayElemTypeDef, ColumnOption, DataType, Expr, ObjectName,
+    SchemaName, Statement, TableConstraint,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::ops::ControlFlow;
+
+use crate::lexer;
+use crate::repo::import_edges;
+
+/// The database objects defined and referenced by a script.
+/// Objects are identified by their schema-qualified name, and schemas by `schema:<name>`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ObjectReferences {
+    pub defined: BTreeSet<String>,
+    pub referenced: BTreeSet<String>,
+    /// Number of statements which could not be parsed, and were ignored
+    pub unparsed: usize,
+}
+
+impl ObjectReferences {
+    pub fn analyze(script: &str) -> ObjectReferences {
+        let mut objects = ObjectReferences::default();
+
+        // psql meta-commands are not SQL
+        let script = script
+            .lines()
+            .map(|line| {
+                if line.trim_start().starts_with('\\') {
+                    ""
+                } else {
+                    line
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        for statement in lexer::split_statements(&script) {
+            match Parser::parse_sql(&PostgreSqlDialect {}, statement) {
+                Ok(parsed) => parsed.iter().for_each(|s| objects.add_statement(s)),
+                Err(_) => objects.unparsed += 1,
+            }
+        }
+
+        let defined = &objects.defined;
+        objects.referenced.retain(|name| !defined.contains(name));
+        objects
+    }
+
+    fn add_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::CreateSchema {
+                schema_name: SchemaName::Simple(name) | SchemaName::NamedAuthorization(name, _),
+                ..
+            } => {
+                self.defined
+                    .insert(schema_key(&ident_value(&name.0[name.0.len() - 1])));
+            }
+            Statement::CreateTable(table) => {
+                self.define(&table.name);
+                for column in &table.columns {
+                    self.reference_type(&column.data_type);
+                    for option in &column.options {
+                        if let ColumnOption::ForeignKey { foreign_table, .. } = &option.option {
+                            self.reference(foreign_table);
+                        }
+                    }
+                }
+                for constraint in &table.constraints {
+                    if let TableConstraint::ForeignKey { foreign_table, .. } = constraint {
+                        self.reference(foreign_table);
+                    }
+                }
+            }
+            Statement::CreateView { name, .. }
+            | Statement::CreateType { name, .. }
+            | Statement::CreateSequence { name, .. } => self.define(name),
+            Statement::CreateFunction(function) => {
+                self.define(&function.name);
+                for arg in function.args.iter().flatten() {
+                    self.reference_type(&arg.data_type);
+                }
+                if let Some(return_type) = &function.return_type {
+                    self.reference_type(return_type);
+                }
+            }
+            _ => {}
+        }
+
+        let _ = visit_relations(statement, |relation| {
+            self.reference(relation);
+            ControlFlow::<()>::Continue(())
+        });
+        let _ = visit_expressions(statement, |expr| {
+            if let Expr::Function(function) = expr {
+                self.reference(&function.name);
+            }
+            ControlFlow::<()>::Continue(())
+        });
+    }
+
+    fn define(&mut self, name: &ObjectName) {
+        if let Some(schema) = schema_name(name) {
+            self.referenced.insert(schema_key(&schema));
+        }
+        self.defined.insert(object_key(name));
+    }
+
+    fn reference(&mut self, name: &ObjectName) {
+        if let Some(schema) = schema_name(name) {
+            self.referenced.insert(schema_key(&schema));
+        }
+        self.referenced.insert(object_key(name));
+    }
+
+    fn reference_type(&mut self, data_type: &DataType) {
+        match data_type {
+            DataType::Custom(name, _) => self.reference(name),
+            DataType::Array(
+                ArrayElemTypeDef::SquareBracket(inner, _)
+                | ArrayElemTypeDef::AngleBracket(inner)
+                | ArrayElemTypeDef::Parenthesis(inner),
+            ) => self.reference_type(inner),
+            _ => {}
+        }
+    }
+}
+
+pub fn ident_value(ident: &sqlparser::ast::Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+fn schema_name(name: &ObjectName) -> Option<String> {
+    match name.0.len() {
+        0 | 1 => None,
+        len => Some(ident_value(&name.0[len - 2])),
+    }
+}
+
+pub fn object_key(name: &ObjectName) -> String {
+    let object = name.0.last().map(ident_value).unwrap_or_default();
+    format!(
+        "{}.{}",
+        schema_name(name).unwrap_or_else(|| "public".to_string()),
+        object
+    )
+}
+
+fn schema_key(schema: &str) -> String {
+    format!("schema:{}", schema)
+}
+
+/// Infers the dependencies between scripts from the objects they define and reference.
+/// Returns `(dependency, dependent)` pairs of script paths.
+pub fn infer_edges(sql_scripts: &HashMap<&str, &str>) -> Vec<(String, String)> {
+    let analyzed = analyze_scripts(sql_scripts);
+    let definitions = definitions(&analyzed);
+
+    let mut edges = vec![];
+    for (path, objects) in &analyzed {
+        for object in &objects.referenced {
+            if let Some(definition_path) = definitions.get(object.as_str()) {
+                if definition_path != path {
+                    edges.push((definition_path.to_string(), path.to_string()));
+                }
+            }
+        }
+    }
+    edges.sort();
+    edges.dedup();
+    edges
+}
+
+fn analyze_scripts<'a>(sql_scripts: &HashMap<&'a str, &str>) -> Vec<(&'a str, ObjectReferences)> {
+    let mut analyzed: Vec<(&str, ObjectReferences)> = sql_scripts
+        .iter()
+        .map(|(path, script)| (*path, ObjectReferences::analyze(script)))
+        .collect();
+    analyzed.sort_by_key(|(path, _)| *path);
+    analyzed
+}
+
+/// Maps each object to the first script defining it, in lexicographic order
+fn definitions<'a>(analyzed: &'a [(&'a str, ObjectReferences)]) -> HashMap<&'a str, &'a str> {
+    let mut definitions = HashMap::new();
+    for (path, objects) in analyzed {
+        for object in &objects.defined {
+            definitions.entry(object.as_str()).or_insert(*path);
+        }
+    }
+    definitions
+}
+
+/// An issue with the `-- import` comments of a script
+#[derive(Debug, PartialEq, Eq)]
+pub enum ImportLint {
+    /// The script references objects defined in a file it does not import, directly or transitively
+    Missing {
+        path: String,
+        import: String,
+        objects: Vec<String>,
+    },
+    /// The script imports a file without referencing any of the objects it defines
+    Redundant { path: String, import: String },
+}
+
+impl fmt::Display for ImportLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportLint::Missing {
+                path,
+                import,
+                objects,
+            } => write!(
+                f,
+                "{}: missing import {} (references {})",
+                path,
+                import,
+                objects.join(", ")
+            ),
+            ImportLint::Redundant { path, import } => {
+                write!(f, "{}: redundant import {}", path, import)
+            }
+        }
+    }
+}
+
+pub fn lint_imports(sql_scripts: &HashMap<&str, &str>) -> Vec<ImportLint> {
+    let analyzed = analyze_scripts(sql_scripts);
+    let definitions = definitions(&analyzed);
+
+    let mut imports: HashMap<String, Vec<String>> = HashMap::new();
+    for (dependency, dependent) in import_edges(sql_scripts) {
+        imports.entry(dependent).or_default().push(dependency);
+    }
+
+    let mut lints = vec![];
+    for (path, objects) in &analyzed {
+        let direct_imports = imports.get(*path).cloned().unwrap_or_default();
+
+        let mut imported = HashSet::new();
+        let mut stack = direct_imports.clone();
+        while let Some(import) = stack.pop() {
+            if let Some(transitive) = imports.get(&import) {
+                if !imported.contains(&import) {
+                    stack.extend(transitive.iter().cloned());
+                }
+            }
+            imported.insert(import);
+        }
+
+        let mut missing: HashMap<&str, Vec<String>> = HashMap::new();
+        for object in &objects.referenced {
+            if let Some(definition_path) = definitions.get(object.as_str()) {
+                if definition_path != path && !imported.contains(*definition_path) {
+                    missing
+                        .entry(definition_path)
+                        .or_default()
+                        .push(object.clone());
+                }
+            }
+        }
+        let mut missing: Vec<_> = missing.into_iter().collect();
+        missing.sort();
+        lints.extend(
+            missing
+                .into_iter()
+                .map(|(import, objects)| ImportLint::Missing {
+                    path: path.to_string(),
+                    import: import.to_string(),
+                    objects,
+                }),
+        );
+
+        // imports can't be deemed redundant if part of the script wasn't analyzed
+        if objects.unparsed > 0 {
+            continue;
+        }
+        for import in direct_imports {
+            let is_used =
+                analyzed
+                    .iter()
+                    .find(|(p, _)| *p == import)
+                    .is_some_and(|(_, imported_objects)| {
+                        imported_objects.unparsed > 0
+                            || imported_objects
+                                .defined
+                                .iter()
+                                .any(|o| objects.referenced.contains(o))
+                    });
+            if !is_used {
+                lints.push(ImportLint::Redundant {
+                    path: path.to_string(),
+                    import,
+                });
+            }
+        }
+    }
+
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_defined_and_referenced_objects() {
+        let objects = ObjectReferences::analyze(
+            r#"
+\set role app_user
+create type my_app.status as enum ('todo', 'done');
+create table my_app.todo (
+  id int primary key,
+  status my_app.status,
+  tags my_app.tag[],
+  created_by int references my_app.user(id)
+);
+create view my_app.open_todo as select * from my_app.todo join my_app.assignment using (id);
+create function my_app.current_user_id() returns my_app.user_id as $$ select 1 $$ language sql;
+create index on my_app.todo(status);
+grant select on my_app.todo to :role;
+"#,
+        );
+
+        assert_eq!(
+            BTreeSet::from(
+                [
+                    "my_app.status",
+                    "my_app.todo",
+                    "my_app.open_todo",
+                    "my_app.current_user_id"
+                ]
+                .map(String::from)
+            ),
+            objects.defined
+        );
+        assert_eq!(
+            BTreeSet::from(
+                [
+                    "schema:my_app",
+                    "my_app.tag",
+                    "my_app.user",
+                    "my_app.assignment",
+                    "my_app.user_id"
+                ]
+                .map(String::from)
+            ),
+            objects.referenced
+        );
+        assert_eq!(1, objects.unparsed);
+    }
+
+    #[test]
+    fn it_infers_edges() {
+        let scripts = HashMap::from([
+            (
+                "a_todo.sql",
+                "create table app.todo (user_id int references app.user);",
+            ),
+            ("b_user.sql", "create table app.user (id int primary key);"),
+            ("c_schema.sql", "create schema app;"),
+        ]);
+
+        assert_eq!(
+            vec![
+                ("b_user.sql".to_string(), "a_todo.sql".to_string()),
+                ("c_schema.sql".to_string(), "a_todo.sql".to_string()),
+                ("c_schema.sql".to_string(), "b_user.sql".to_string()),
+            ],
+            infer_edges(&scripts)
+        );
+    }
+
+    #[test]
+    fn it_lints_imports() {
+        let scripts = HashMap::from([
+            ("schema.sql", "create schema app;"),
+            ("types.sql", "create type app.status as enum ('a');"),
+            (
+                "user.sql",
+                "-- import schema.sql\ncreate table app.user (id int primary key);",
+            ),
+            (
+                "todo.sql",
+                "-- import user.sql\n-- import types.sql\ncreate table app.todo (user_id int references app.user);",
+            ),
+        ]);
+
+        assert_eq!(
+            vec![
+                ImportLint::Redundant {
+                    path: "todo.sql".to_string(),
+                    import: "types.sql".to_string()
+                },
+                ImportLint::Missing {
+                    path: "types.sql".to_string(),
+                    import: "schema.sql".to_string(),
+                    objects: vec!["schema:app".to_string()]
+                },
+            ],
+            lint_imports(&scripts)
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use glob::Pattern;
+use sqlparser::ast::{AlterColumnOperation, AlterTableOperation, ObjectType, Statement};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::fmt;
+
+use crate::dependencies::{ident_value, object_key};
+use crate::lexer;
+
+/// A kind of statement which may lose data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    DropSchema,
+    DropTable,
+    DropColumn,
+    ChangeColumnType,
+    DropConstraint,
+    DropSequence,
+    DropType,
+    Truncate,
+    /// A statement starting with `drop`, `truncate` or `alter` which could not be parsed
+    Unrecognized,
+}
+
+impl ChangeKind {
+    /// The data which may be lost
+    pub fn risk(&self) -> &'static str {
+        match self {
+            ChangeKind::DropSchema => "all the objects and data of the schema",
+            ChangeKind::DropTable => "all the rows of the table",
+            ChangeKind::DropColumn => "the values of the column",
+            ChangeKind::ChangeColumnType => {
+                "values which do not fit the new type may be truncated, or the statement may fail"
+            }
+            ChangeKind::DropConstraint => "the guarantees the constraint provided",
+            ChangeKind::DropSequence => "the current value of the sequence",
+            ChangeKind::DropType => "the columns using the type",
+            ChangeKind::Truncate => "all the rows of the table",
+            ChangeKind::Unrecognized => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeKind::DropSchema => "drop schema",
+            ChangeKind::DropTable => "drop table",
+            ChangeKind::DropColumn => "drop column",
+            ChangeKind::ChangeColumnType => "change column type",
+            ChangeKind::DropConstraint => "drop constraint",
+            ChangeKind::DropSequence => "drop sequence",
+            ChangeKind::DropType => "drop type",
+            ChangeKind::Truncate => "truncate",
+            ChangeKind::Unrecognized => "unrecognized statement",
+        })
+    }
+}
+
+/// A destructive change of a migration.
+/// Objects are identified by their schema-qualified name, e.g. `app.user` or `app.user.email` for a column.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DestructiveChange<'a> {
+    pub kind: ChangeKind,
+    pub object: String,
+    pub statement: &'a str,
+}
+
+impl DestructiveChange<'_> {
+    /// Whether the object matches one of the patterns, e.g. `app.legacy_*`
+    pub fn is_allowed(&self, allow_list: &[String]) -> bool {
+        allow_list
+            .iter()
+            .any(|pattern| Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(&self.object)))
+    }
+}
+
+impl fmt::Display for DestructiveChange<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.kind, self.object, self.kind.risk())
+    }
+}
+
+pub fn find_destructive_changes(script: &str) -> Vec<DestructiveChange<'_>> {
+    let mut changes = vec![];
+    for statement in lexer::split_statements(script) {
+        let text = lexer::strip_leading_comments(statement);
+        let parsed = match Parser::parse_sql(&PostgreSqlDialect {}, text) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                let keyword = text.split_whitespace().next().unwrap_or_default();
+                if ["drop", "truncate", "alter"].contains(&keyword.to_lowercase().as_str()) {
+                    changes.push(DestructiveChange {
+                        kind: ChangeKind::Unrecognized,
+                        object: unparsed_object(text).unwrap_or_else(|| "?".to_string()),
+                        statement: text,
+                    });
+                }
+                continue;
+            }
+        };
+
+        let mut push = |kind, object| {
+            changes.push(DestructiveChange {
+                kind,
+                object,
+                statement: text,
+            })
+        };
+        for parsed in parsed {
+            match parsed {
+                Statement::Drop {
+                    object_type, names, ..
+                } => {
+                    let kind = match object_type {
+                        ObjectType::Schema => ChangeKind::DropSchema,
+                        ObjectType::Table => ChangeKind::DropTable,
+                        ObjectType::Sequence => ChangeKind::DropSequence,
+                        ObjectType::Type => ChangeKind::DropType,
+                        _ => continue,
+                    };
+                    for name in &names {
+                        let object = match kind {
+                            ChangeKind::DropSchema => {
+                                name.0.last().map(ident_value).unwrap_or_default()
+                            }
+                            _ => object_key(name),
+                        };
+                        push(kind, object);
+                    }
+                }
+                Statement::AlterTable {
+                    name, operations, ..
+                } => {
+                    let table = object_key(&name);
+                    for operation in operations {
+                        match operation {
+                            AlterTableOperation::DropColumn { column_name, .. } => push(
+                                ChangeKind::DropColumn,
+                                format!("{}.{}", table, ident_value(&column_name)),
+                            ),
+                            AlterTableOperation::AlterColumn {
+                                column_name,
+                                op: AlterColumnOperation::SetDataType { .. },
+                            } => push(
+                                ChangeKind::ChangeColumnType,
+                                format!("{}.{}", table, ident_value(&column_name)),
+                            ),
+                            AlterTableOperation::DropConstraint { name, .. } => push(
+                                ChangeKind::DropConstraint,
+                                format!("{}.{}", table, ident_value(&name)),
+                            ),
+                            AlterTableOperation::DropPrimaryKey => {
+                                push(ChangeKind::DropConstraint, format!("{}.pkey", table))
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Statement::Truncate { table_names, .. } => {
+                    for target in &table_names {
+                        push(ChangeKind::Truncate, object_key(&target.name));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    changes
+}
+
+/// Words which may precede the name of the object in `drop`, `truncate` and `alter` statements
+const OBJECT_KIND_WORDS: &[&str] = &[
+    "access",
+    "aggregate",
+    "by",
+    "cast",
+    "class",
+    "collation",
+    "concurrently",
+    "configuration",
+    "conversion",
+    "database",
+    "default",
+    "dictionary",
+    "domain",
+    "event",
+    "exists",
+    "extension",
+    "family",
+    "for",
+    "foreign",
+    "function",
+    "group",
+    "if",
+    "index",
+    "language",
+    "large",
+    "mapping",
+    "materialized",
+    "method",
+    "object",
+    "only",
+    "operator",
+    "owned",
+    "parser",
+    "policy",
+    "procedural",
+    "procedure",
+    "publication",
+    "role",
+    "routine",
+    "rule",
+    "schema",
+    "search",
+    "sequence",
+    "server",
+    "statistics",
+    "subscription",
+    "table",
+    "tablespace",
+    "template",
+    "text",
+    "transform",
+    "trigger",
+    "type",
+    "user",
+    "view",
+];
+
+/// Returns the name of the object of a statement which could not be parsed,
+/// e.g. `app.mv` for `drop materialized view if exists app.mv`
+fn unparsed_object(text: &str) -> Option<String> {
+    let tokens = Tokenizer::new(&PostgreSqlDialect {}, text)
+        .tokenize()
+        .ok()?;
+    let mut parts: Vec<String> = vec![];
+    let mut after_period = false;
+    // skips the drop, truncate or alter keyword
+    for token in tokens
+        .into_iter()
+        .filter(|t| !matches!(t, Token::Whitespace(_)))
+        .skip(1)
+    {
+        match token {
+            Token::Word(word)
+                if parts.is_empty()
+                    && word.quote_style.is_none()
+                    && OBJECT_KIND_WORDS.contains(&word.value.to_lowercase().as_str()) => {}
+            Token::Word(word) if parts.is_empty() || after_period => {
+                parts.push(match word.quote_style {
+                    Some(_) => word.value,
+                    None => word.value.to_lowercase(),
+                });
+                after_period = false;
+            }
+            Token::Period if !parts.is_empty() && !after_period => after_period = true,
+            _ => break,
+        }
+    }
+    match parts.as_slice() {
+        [] => None,
+        [name] => Some(format!("public.{}", name)),
+        [.., schema, name] => Some(format!("{}.{}", schema, name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_destructive_changes() {
+        let script = r#"
+drop table "app"."legacy";
+alter table "app"."user" drop column "nickname";
+alter table "app"."user" alter column "email" set data type varchar(64) using "email"::varchar(64);
+alter table "app"."user" drop constraint "user_email_key", add column "age" int;
+drop view if exists "app"."user_view";
+create table app.new (id int);
+truncate app.audit;
+drop schema old cascade;
+drop sequence app.counter;
+drop type "app"."Mood";
+"#;
+
+        let changes = find_destructive_changes(script);
+        assert_eq!(
+            vec![
+                (ChangeKind::DropTable, "app.legacy"),
+                (ChangeKind::DropColumn, "app.user.nickname"),
+                (ChangeKind::ChangeColumnType, "app.user.email"),
+                (ChangeKind::DropConstraint, "app.user.user_email_key"),
+                (ChangeKind::Truncate, "app.audit"),
+                (ChangeKind::DropSchema, "old"),
+                (ChangeKind::DropSequence, "app.counter"),
+                (ChangeKind::DropType, "app.Mood"),
+            ],
+            changes
+                .iter()
+                .map(|change| (change.kind, change.object.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(r#"drop table "app"."legacy";"#, changes[0].statement);
+    }
+
+    #[test]
+    fn it_flags_statements_which_cannot_be_parsed() {
+        let script = r#"
+drop materialized view if exists "app"."Report";
+alter table "public"."t" drop column "c", add constraint "x" check (a > 0) not valid;
+"#;
+
+        let changes = find_destructive_changes(script);
+        assert_eq!(
+            vec![
+                (ChangeKind::Unrecognized, "app.Report"),
+                (ChangeKind::Unrecognized, "public.t"),
+            ],
+            changes
+                .iter()
+                .map(|change| (change.kind, change.object.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert!(changes[0].is_allowed(&["app.*".to_string()]));
+    }
+
+    #[test]
+    fn it_allows_changes_matching_the_allow_list() {
+        let changes = find_destructive_changes("drop table app.legacy_orders;");
+        assert!(changes[0].is_allowed(&["app.legacy_*".to_string()]));
+        assert!(!changes[0].is_allowed(&["app.orders".to_string()]));
+    }
+}
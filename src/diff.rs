@@ -1,8 +1,10 @@
 use std::process::Command;
 
 use anyhow::{bail, Result};
+use std::collections::HashMap;
 
 use crate::config::DiffEngineConfig;
+use crate::template;
 
 fn run_migra(source: &String, target: &String) -> Result<String> {
     let output = Command::new("migra")
@@ -16,12 +18,21 @@ fn run_migra(source: &String, target: &String) -> Result<String> {
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
-pub fn run_diff_command(config: &DiffEngineConfig) -> Result<String> {
+/// Name of the diff engine, recorded with deployments
+pub fn engine_name(config: &DiffEngineConfig) -> &str {
+    config.command.as_deref().unwrap_or("migra")
+}
+
+pub fn run_diff_command(
+    config: &DiffEngineConfig,
+    vars: &HashMap<String, String>,
+) -> Result<String> {
     let source = config.source.to_url();
     let target = config.target.to_url();
 
     match &config.command {
         Some(command) => {
+            let command = template::expand("diff_engine.command", command, vars)?;
             let output = Command::new("sh")
                 .arg("-c")
                 .arg(command)
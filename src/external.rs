@@ -0,0 +1,123 @@
+use anyhow::{bail, Result};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::config::RepositoryConfig;
+use crate::repo::{import_edges, SchemaRepository};
+
+/// A file imported from another repository with `-- import @<repository>:<ref>:<path>`
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExternalImport<'a> {
+    pub repository: &'a str,
+    pub git_ref: &'a str,
+    pub path: &'a str,
+}
+
+impl<'a> ExternalImport<'a> {
+    pub fn parse(import: &'a str) -> Option<ExternalImport<'a>> {
+        let mut parts = import.strip_prefix('@')?.splitn(3, ':');
+        let import = ExternalImport {
+            repository: parts.next()?,
+            git_ref: parts.next()?,
+            path: parts.next()?,
+        };
+        if import.repository.is_empty() || import.git_ref.is_empty() || import.path.is_empty() {
+            return None;
+        }
+        Some(import)
+    }
+}
+
+/// Splits the key of a script into its `@<repository>:<ref>:` prefix, empty for local files, and its path
+pub fn split_key(key: &str) -> (&str, &str) {
+    match ExternalImport::parse(key) {
+        Some(import) => key.split_at(key.len() - import.path.len()),
+        None => ("", key),
+    }
+}
+
+/// Adds the files imported from other repositories to the scripts, along with the files they import, transitively.
+/// They are keyed by their import, e.g. `@platform:v2.3:schema/audit.sql`.
+pub fn load_external_imports(
+    scripts: &mut HashMap<String, String>,
+    repositories: &HashMap<String, RepositoryConfig>,
+) -> Result<()> {
+    let mut opened: HashMap<&str, SchemaRepository> = HashMap::new();
+
+    loop {
+        let sql_scripts: HashMap<&str, &str> = scripts
+            .iter()
+            .map(|(path, script)| (path.as_str(), script.as_str()))
+            .collect();
+        let missing: BTreeSet<(String, String)> = import_edges(&sql_scripts)
+            .into_iter()
+            .filter(|(import, _)| import.starts_with('@') && !scripts.contains_key(import))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        for (import, dependent) in missing {
+            if scripts.contains_key(&import) {
+                continue;
+            }
+            let external = match ExternalImport::parse(&import) {
+                Some(external) => external,
+                None => bail!(
+                    "{}: invalid import {}, expected @<repository>:<ref>:<path>",
+                    dependent,
+                    import
+                ),
+            };
+            let (name, config) = match repositories.get_key_value(external.repository) {
+                Some(repository) => repository,
+                None => bail!(
+                    "{}: repository {} is not defined in postgit.toml",
+                    dependent,
+                    external.repository
+                ),
+            };
+            if !opened.contains_key(name.as_str()) {
+                opened.insert(name, SchemaRepository::open(&config.path)?);
+            }
+
+            let mut files = opened[name.as_str()].schema_files(external.git_ref, external.path)?;
+            match files.scripts.remove(external.path) {
+                Some(text) => {
+                    scripts.insert(import, text);
+                }
+                None => bail!(
+                    "{}: file {} does not exist at {} in repository {}",
+                    dependent,
+                    external.path,
+                    external.git_ref,
+                    name
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_external_imports() {
+        assert_eq!(
+            Some(ExternalImport {
+                repository: "platform",
+                git_ref: "v2.3",
+                path: "schema/audit.sql"
+            }),
+            ExternalImport::parse("@platform:v2.3:schema/audit.sql")
+        );
+        assert_eq!(None, ExternalImport::parse("@platform:schema/audit.sql"));
+        assert_eq!(None, ExternalImport::parse("schema/audit.sql"));
+
+        assert_eq!(
+            ("@platform:v2.3:", "schema/audit.sql"),
+            split_key("@platform:v2.3:schema/audit.sql")
+        );
+        assert_eq!(("", "schema/audit.sql"), split_key("schema/audit.sql"));
+    }
+}
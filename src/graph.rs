@@ -0,0 +1,194 @@
+use petgraph::prelude::DiGraphMap;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::cli::GraphFormat;
+use crate::repo::EdgeKind;
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<&'a str>,
+    edges: Vec<JsonEdge<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<&'a [&'a str]>,
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    kind: &'static str,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Import => "import",
+            EdgeKind::Inferred => "inferred",
+            EdgeKind::Manifest => "manifest",
+            EdgeKind::Lexicographic => "lexicographic",
+        }
+    }
+}
+
+/// Renders the dependency graph, with edges going from a dependency to its dependent.
+/// If the load order is given, nodes are numbered accordingly.
+pub fn render(
+    graph: &DiGraphMap<&str, EdgeKind>,
+    order: Option<&[&str]>,
+    format: GraphFormat,
+) -> String {
+    let mut nodes: Vec<&str> = graph.nodes().collect();
+    nodes.sort();
+    let mut edges: Vec<(&str, &str, EdgeKind)> = graph
+        .all_edges()
+        .map(|(a, b, kind)| (a, b, *kind))
+        .collect();
+    edges.sort_by_key(|(a, b, _)| (*a, *b));
+
+    let positions: HashMap<&str, usize> = order
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (*node, i + 1))
+        .collect();
+    let label = |node: &str| match positions.get(node) {
+        Some(position) => format!("{}. {}", position, node),
+        None => node.to_string(),
+    };
+
+    match format {
+        GraphFormat::Dot => {
+            let mut output = String::from("digraph schema {\n");
+            for node in &nodes {
+                output += &format!(
+                    "  {} [label={}];\n",
+                    dot_quote(node),
+                    dot_quote(&label(node))
+                );
+            }
+            for (from, to, kind) in &edges {
+                let attributes = match kind {
+                    EdgeKind::Import => String::new(),
+                    EdgeKind::Inferred => " [style=dotted, label=\"inferred\"]".to_string(),
+                    EdgeKind::Manifest => " [style=bold, label=\"manifest\"]".to_string(),
+                    EdgeKind::Lexicographic => {
+                        " [style=dashed, color=gray, label=\"lexicographic\"]".to_string()
+                    }
+                };
+                output += &format!(
+                    "  {} -> {}{};\n",
+                    dot_quote(from),
+                    dot_quote(to),
+                    attributes
+                );
+            }
+            output + "}"
+        }
+        GraphFormat::Mermaid => {
+            let ids: HashMap<&str, usize> =
+                nodes.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+            let mut output = String::from("flowchart TD\n");
+            for node in &nodes {
+                output += &format!(
+                    "  n{}[\"{}\"]\n",
+                    ids[node],
+                    label(node).replace('"', "#quot;")
+                );
+            }
+            for (from, to, kind) in &edges {
+                let arrow = match kind {
+                    EdgeKind::Import => "-->",
+                    EdgeKind::Inferred => "-.->|inferred|",
+                    EdgeKind::Manifest => "==>|manifest|",
+                    EdgeKind::Lexicographic => "-.->|lexicographic|",
+                };
+                output += &format!("  n{} {} n{}\n", ids[from], arrow, ids[to]);
+            }
+            output.trim_end().to_string()
+        }
+        GraphFormat::Json => {
+            let json = JsonGraph {
+                edges: edges
+                    .iter()
+                    .map(|(from, to, kind)| JsonEdge {
+                        from,
+                        to,
+                        kind: kind.name(),
+                    })
+                    .collect(),
+                nodes,
+                order,
+            };
+            serde_json::to_string_pretty(&json).unwrap()
+        }
+    }
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_graph() -> DiGraphMap<&'static str, EdgeKind> {
+        let mut graph = DiGraphMap::new();
+        graph.add_edge("schema.sql", "user.sql", EdgeKind::Import);
+        graph.add_edge("user.sql", "todo.sql", EdgeKind::Inferred);
+        graph.add_edge("todo.sql", "view.sql", EdgeKind::Lexicographic);
+        graph
+    }
+
+    #[test]
+    fn it_renders_dot() {
+        let order = ["schema.sql", "user.sql", "todo.sql", "view.sql"];
+
+        assert_eq!(
+            r#"digraph schema {
+  "schema.sql" [label="1. schema.sql"];
+  "todo.sql" [label="3. todo.sql"];
+  "user.sql" [label="2. user.sql"];
+  "view.sql" [label="4. view.sql"];
+  "schema.sql" -> "user.sql";
+  "todo.sql" -> "view.sql" [style=dashed, color=gray, label="lexicographic"];
+  "user.sql" -> "todo.sql" [style=dotted, label="inferred"];
+}"#,
+            render(&sample_graph(), Some(&order), GraphFormat::Dot)
+        );
+    }
+
+    #[test]
+    fn it_renders_mermaid() {
+        assert_eq!(
+            r#"flowchart TD
+  n0["schema.sql"]
+  n1["todo.sql"]
+  n2["user.sql"]
+  n3["view.sql"]
+  n0 --> n2
+  n1 -.->|lexicographic| n3
+  n2 -.->|inferred| n1"#,
+            render(&sample_graph(), None, GraphFormat::Mermaid)
+        );
+    }
+
+    #[test]
+    fn it_renders_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&sample_graph(), None, GraphFormat::Json)).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "nodes": ["schema.sql", "todo.sql", "user.sql", "view.sql"],
+                "edges": [
+                    {"from": "schema.sql", "to": "user.sql", "kind": "import"},
+                    {"from": "todo.sql", "to": "view.sql", "kind": "lexicographic"},
+                    {"from": "user.sql", "to": "todo.sql", "kind": "inferred"},
+                ]
+            }),
+            json
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::{bail, Result};
+use sqlparser::ast::{
+    AlterColumnOperation, AlterTableOperation, ColumnOption, ObjectType, Statement, TableConstraint,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::db::TableStats;
+use crate::dependencies::object_key;
+use crate::lexer;
+use crate::lint::is_volatile;
+
+/// Table lock levels, from the weakest to the strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    ShareUpdateExclusive,
+    Share,
+    ShareRowExclusive,
+    AccessExclusive,
+}
+
+impl fmt::Display for LockLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LockLevel::ShareUpdateExclusive => "SHARE UPDATE EXCLUSIVE",
+            LockLevel::Share => "SHARE",
+            LockLevel::ShareRowExclusive => "SHARE ROW EXCLUSIVE",
+            LockLevel::AccessExclusive => "ACCESS EXCLUSIVE",
+        })
+    }
+}
+
+/// A lock a statement takes on an existing table
+#[derive(Debug, PartialEq, Eq)]
+pub struct TableLock {
+    pub table: String,
+    pub level: LockLevel,
+    /// Whether the statement rewrites the whole table
+    pub rewrite: bool,
+}
+
+#[derive(Debug)]
+pub struct StatementImpact<'a> {
+    pub statement: &'a str,
+    pub locks: Vec<TableLock>,
+    /// Whether the statement could be parsed, and its locks are known
+    pub parsed: bool,
+}
+
+pub fn analyze(script: &str) -> Vec<StatementImpact<'_>> {
+    lexer::split_statements(script)
+        .into_iter()
+        .map(|statement| {
+            let statement = lexer::strip_leading_comments(statement);
+            match Parser::parse_sql(&PostgreSqlDialect {}, statement) {
+                Ok(parsed) => StatementImpact {
+                    statement,
+                    locks: parsed.iter().flat_map(statement_locks).collect(),
+                    parsed: true,
+                },
+                Err(_) => StatementImpact {
+                    statement,
+                    locks: vec![],
+                    parsed: false,
+                },
+            }
+        })
+        .collect()
+}
+
+/// The tables locked by the statements
+pub fn locked_tables(impacts: &[StatementImpact]) -> Vec<String> {
+    impacts
+        .iter()
+        .flat_map(|impact| impact.locks.iter().map(|lock| lock.table.clone()))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn statement_locks(statement: &Statement) -> Vec<TableLock> {
+    let lock = |table: String, level, rewrite| TableLock {
+        table,
+        level,
+        rewrite,
+    };
+    match statement {
+        Statement::CreateIndex(index) => vec![lock(
+            object_key(&index.table_name),
+            if index.concurrently {
+                LockLevel::ShareUpdateExclusive
+            } else {
+                LockLevel::Share
+            },
+            false,
+        )],
+        Statement::Drop {
+            object_type: ObjectType::Table,
+            names,
+            ..
+        } => names
+            .iter()
+            .map(|name| lock(object_key(name), LockLevel::AccessExclusive, false))
+            .collect(),
+        Statement::Truncate { table_names, .. } => table_names
+            .iter()
+            .map(|target| lock(object_key(&target.name), LockLevel::AccessExclusive, false))
+            .collect(),
+        Statement::AlterTable {
+            name, operations, ..
+        } => {
+            let table = object_key(name);
+            let mut level = LockLevel::ShareUpdateExclusive;
+            let mut rewrite = false;
+            let mut locks = vec![];
+            for operation in operations {
+                let operation_level = match operation {
+                    AlterTableOperation::AddColumn { column_def, .. } => {
+                        rewrite |= column_def.options.iter().any(|o| {
+                            matches!(&o.option, ColumnOption::Default(expr) if is_volatile(expr))
+                        });
+                        LockLevel::AccessExclusive
+                    }
+                    AlterTableOperation::AlterColumn {
+                        op: AlterColumnOperation::SetDataType { .. },
+                        ..
+                    } => {
+                        rewrite = true;
+                        LockLevel::AccessExclusive
+                    }
+                    AlterTableOperation::AddConstraint(TableConstraint::ForeignKey {
+                        foreign_table,
+                        ..
+                    }) => {
+                        locks.push(lock(
+                            object_key(foreign_table),
+                            LockLevel::ShareRowExclusive,
+                            false,
+                        ));
+                        LockLevel::ShareRowExclusive
+                    }
+                    _ => LockLevel::AccessExclusive,
+                };
+                level = level.max(operation_level);
+            }
+            locks.insert(0, lock(table, level, rewrite));
+            locks
+        }
+        _ => vec![],
+    }
+}
+
+/// Describes the locks each statement takes, along with the size of the tables
+pub fn describe(impacts: &[StatementImpact], stats: &HashMap<String, TableStats>) -> String {
+    let mut description = String::new();
+    for impact in impacts {
+        if impact.parsed && impact.locks.is_empty() {
+            continue;
+        }
+        description.push_str(&format!("  {}\n", first_line(impact.statement)));
+        if !impact.parsed {
+            description.push_str("    unknown locks, the statement could not be parsed\n");
+        }
+        for lock in &impact.locks {
+            let size = match stats.get(&lock.table) {
+                Some(stats) => format!("~{} rows, {}", stats.rows, format_size(stats.size)),
+                None => "new table".to_string(),
+            };
+            description.push_str(&format!(
+                "    {}: {} lock{}, {}\n",
+                lock.table,
+                lock.level,
+                if lock.rewrite { ", rewrite" } else { "" },
+                size
+            ));
+        }
+    }
+    description
+}
+
+/// Fails if a statement rewrites a table larger than `max_size` bytes, or if an `alter table` statement which may
+/// rewrite a table could not be parsed
+pub fn check_rewrite_size(
+    impacts: &[StatementImpact],
+    stats: &HashMap<String, TableStats>,
+    max_size: u64,
+) -> Result<()> {
+    for impact in impacts.iter().filter(|impact| !impact.parsed) {
+        let words: Vec<String> = impact
+            .statement
+            .split_whitespace()
+            .take(2)
+            .map(str::to_lowercase)
+            .collect();
+        if words == ["alter", "table"] {
+            bail!(
+                "The tables this statement may rewrite are unknown, as it could not be parsed. Push without --max-rewrite-size to apply it: {}",
+                first_line(impact.statement)
+            );
+        }
+    }
+    for lock in impacts.iter().flat_map(|impact| &impact.locks) {
+        let size = stats.get(&lock.table).map_or(0, |stats| stats.size);
+        if lock.rewrite && size > max_size {
+            bail!(
+                "{} ({}) would be rewritten, which exceeds --max-rewrite-size ({})",
+                lock.table,
+                format_size(size),
+                format_size(max_size)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn first_line(statement: &str) -> &str {
+    statement.lines().next().unwrap_or_default()
+}
+
+const SIZE_UNITS: &[&str] = &["bytes", "kB", "MB", "GB", "TB"];
+
+/// Formats a size in bytes like `pg_size_pretty`, with powers of 1024
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, SIZE_UNITS[unit])
+    }
+}
+
+/// Parses a size such as `500MB` or `2 GB`, with powers of 1024
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {}", size))?;
+    let exponent = match unit.trim().to_lowercase().as_str() {
+        "" | "b" | "bytes" => 0,
+        "k" | "kb" => 1,
+        "m" | "mb" => 2,
+        "g" | "gb" => 3,
+        "t" | "tb" => 4,
+        _ => {
+            return Err(format!(
+                "invalid size unit {}, expected kB, MB, GB or TB",
+                unit
+            ))
+        }
+    };
+    Ok((number * 1024f64.powi(exponent)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_analyzes_locks() {
+        let impacts = analyze(
+            r#"
+create table app.new (id int);
+create index concurrently on app.user (email);
+CREATE INDEX post_title_idx ON app.post USING btree (title);
+alter table "app"."user" alter column "email" set data type varchar(64);
+alter table "app"."post" add constraint "post_user_fkey" foreign key (user_id) references app.user (id);
+alter table "app"."post" add constraint "post_title_check" check (title <> '') not valid;
+"#,
+        );
+
+        let locks: Vec<Vec<(&str, LockLevel, bool)>> = impacts
+            .iter()
+            .map(|impact| {
+                impact
+                    .locks
+                    .iter()
+                    .map(|lock| (lock.table.as_str(), lock.level, lock.rewrite))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                vec![],
+                vec![("app.user", LockLevel::ShareUpdateExclusive, false)],
+                vec![("app.post", LockLevel::Share, false)],
+                vec![("app.user", LockLevel::AccessExclusive, true)],
+                vec![
+                    ("app.post", LockLevel::ShareRowExclusive, false),
+                    ("app.user", LockLevel::ShareRowExclusive, false)
+                ],
+                vec![],
+            ],
+            locks
+        );
+        assert!(!impacts[5].parsed);
+        assert_eq!(vec!["app.post", "app.user"], locked_tables(&impacts));
+
+        let stats = HashMap::from([(
+            "app.user".to_string(),
+            TableStats {
+                rows: 1200,
+                size: 3 * 1024 * 1024,
+            },
+        )]);
+        assert!(check_rewrite_size(&impacts[..5], &stats, 4 * 1024 * 1024).is_ok());
+        assert_eq!(
+            "app.user (3.0 MB) would be rewritten, which exceeds --max-rewrite-size (1.0 MB)",
+            check_rewrite_size(&impacts[..5], &stats, 1024 * 1024)
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            r#"The tables this statement may rewrite are unknown, as it could not be parsed. Push without --max-rewrite-size to apply it: alter table "app"."post" add constraint "post_title_check" check (title <> '') not valid;"#,
+            check_rewrite_size(&impacts, &stats, 4 * 1024 * 1024)
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(describe(&impacts, &stats)
+            .contains("    app.user: ACCESS EXCLUSIVE lock, rewrite, ~1200 rows, 3.0 MB\n"));
+    }
+
+    #[test]
+    fn it_parses_sizes() {
+        assert_eq!(Ok(500), parse_size("500"));
+        assert_eq!(Ok(2 * 1024 * 1024 * 1024), parse_size("2GB"));
+        assert_eq!(Ok(1536 * 1024), parse_size("1.5 mb"));
+        assert!(parse_size("12 parsecs").is_err());
+        assert_eq!("1.5 MB", format_size(1536 * 1024));
+        assert_eq!("512 bytes", format_size(512));
+    }
+}
@@ -0,0 +1,208 @@
+/// If a comment, a quoted identifier or a (dollar-)quoted string starts at byte `start` of the script,
+/// returns the byte offset where it ends. Line comments end before the newline.
+pub fn literal_end(script: &str, start: usize) -> Option<usize> {
+    let rest = &script[start..];
+    if rest.starts_with("--") {
+        Some(rest.find('\n').map_or(script.len(), |n| start + n))
+    } else if rest.starts_with("/*") {
+        Some(block_comment_end(script, start))
+    } else if rest.starts_with('\'') {
+        let escapes = is_escape_string_prefix(&script[..start]);
+        Some(quote_end(script, start, '\'', escapes))
+    } else if rest.starts_with('"') {
+        Some(quote_end(script, start, '"', false))
+    } else if rest.starts_with('$') && !is_identifier_char_before(&script[..start]) {
+        let tag = dollar_tag(rest)?;
+        Some(
+            script[start + tag.len()..]
+                .find(tag)
+                .map_or(script.len(), |n| start + tag.len() + n + tag.len()),
+        )
+    } else {
+        None
+    }
+}
+
+/// Splits a script into statements, each including its terminating semicolon.
+/// Statements only made of whitespace and comments are omitted.
+/// Semicolons inside `begin atomic ... end` function bodies do not end the statement.
+pub fn split_statements(script: &str) -> Vec<&str> {
+    let mut statements = vec![];
+    let mut chars = script.char_indices().peekable();
+    let mut start = 0;
+    let mut has_code = false;
+    let mut previous_word = String::new();
+    // nesting of `begin atomic` bodies, and of `case` expressions inside them, which also end with `end`
+    let mut atomic_depth = 0;
+    let mut case_depth = 0;
+
+    while let Some((i, c)) = chars.next() {
+        if let Some(end) = literal_end(script, i) {
+            has_code |= !is_comment(&script[i..]);
+            skip_to(&mut chars, end);
+        } else if is_identifier_char(c) && !is_identifier_char_before(&script[..i]) {
+            has_code = true;
+            let end = script[i..]
+                .find(|c: char| !is_identifier_char(c))
+                .map_or(script.len(), |n| i + n);
+            skip_to(&mut chars, end);
+            let word = script[i..end].to_lowercase();
+            match word.as_str() {
+                "atomic" if previous_word == "begin" => atomic_depth += 1,
+                "case" if atomic_depth > 0 => case_depth += 1,
+                "end" if case_depth > 0 => case_depth -= 1,
+                "end" if atomic_depth > 0 => atomic_depth -= 1,
+                _ => {}
+            }
+            previous_word = word;
+        } else if c == ';' && atomic_depth == 0 {
+            if has_code {
+                statements.push(&script[start..i + 1]);
+            }
+            start = i + 1;
+            has_code = false;
+        } else if !c.is_whitespace() {
+            has_code = true;
+        }
+    }
+
+    if has_code {
+        statements.push(&script[start..]);
+    }
+
+    statements
+}
+
+/// Returns the statement without the whitespace and comments preceding it
+pub fn strip_leading_comments(statement: &str) -> &str {
+    let mut rest = statement.trim_start();
+    while is_comment(rest) {
+        let end = literal_end(rest, 0).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest
+}
+
+pub fn skip_to<I: Iterator<Item = (usize, char)>>(chars: &mut std::iter::Peekable<I>, end: usize) {
+    while chars.next_if(|(i, _)| *i < end).is_some() {}
+}
+
+fn is_comment(s: &str) -> bool {
+    s.starts_with("--") || s.starts_with("/*")
+}
+
+fn block_comment_end(script: &str, start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < script.len() {
+        if script[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if script[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += script[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    script.len()
+}
+
+fn quote_end(script: &str, start: usize, quote: char, escapes: bool) -> usize {
+    let mut chars = script[start + 1..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if escapes && c == '\\' {
+            chars.next();
+        } else if c == quote {
+            if chars.peek().map(|(_, c)| *c) == Some(quote) {
+                chars.next();
+            } else {
+                return start + 1 + i + 1;
+            }
+        }
+    }
+    script.len()
+}
+
+fn is_escape_string_prefix(before: &str) -> bool {
+    let mut rev = before.chars().rev();
+    matches!(rev.next(), Some('E' | 'e')) && !rev.next().is_some_and(is_identifier_char)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn is_identifier_char_before(before: &str) -> bool {
+    before.chars().next_back().is_some_and(is_identifier_char)
+}
+
+/// Returns the `$tag$` opening a dollar-quoted string at the start of `s`
+fn dollar_tag(s: &str) -> Option<&str> {
+    let tag_len = s[1..].find(|c: char| !c.is_alphanumeric() && c != '_')?;
+    if s[1 + tag_len..].starts_with('$') && !s[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        Some(&s[..tag_len + 2])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_splits_statements() {
+        let script = r#"
+-- create the schema; first
+create schema app;
+create function app.f() returns text as $body$
+  select 'a;b';
+$body$ language sql;
+/* trailing; comment */
+select E'\';' as "semi;colon""#;
+
+        assert_eq!(
+            vec![
+                "\n-- create the schema; first\ncreate schema app;",
+                "\ncreate function app.f() returns text as $body$\n  select 'a;b';\n$body$ language sql;",
+                "\n/* trailing; comment */\nselect E'\\';' as \"semi;colon\""
+            ],
+            split_statements(script)
+        );
+    }
+
+    #[test]
+    fn it_keeps_begin_atomic_bodies_in_one_statement() {
+        let script = r#"
+create function app.sign(x int) returns text language sql
+begin atomic
+  select case when x < 0 then 'negative' else 'positive' end;
+  select 'end;';
+end;
+create table app.t (begin_atomic int);
+"#;
+
+        assert_eq!(
+            vec![
+                "\ncreate function app.sign(x int) returns text language sql\nbegin atomic\n  select case when x < 0 then 'negative' else 'positive' end;\n  select 'end;';\nend;",
+                "\ncreate table app.t (begin_atomic int);"
+            ],
+            split_statements(script)
+        );
+    }
+
+    #[test]
+    fn it_strips_leading_comments() {
+        assert_eq!(
+            "create index concurrently i on t (c);",
+            strip_leading_comments(
+                "\n-- index\n/* big /* table */ */ create index concurrently i on t (c);"
+            )
+        );
+        assert_eq!("", strip_leading_comments("-- only a comment"));
+    }
+}
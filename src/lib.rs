@@ -1,14 +1,12 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 use notify::RecursiveMode;
 use notify_debouncer_mini::new_debouncer;
-use std::collections::HashMap;
+use std::env;
 use std::ffi::OsStr;
-use std::fs::{self};
 use std::io::{self, Write};
 use std::path::Path;
-use std::time::Duration;
-use walkdir::WalkDir;
+use std::time::{Duration, SystemTime};
 
 pub mod cli;
 pub use cli::*;
@@ -21,29 +19,504 @@ mod diff;
 pub mod db;
 use db::*;
 
+mod dependencies;
+pub use dependencies::ImportLint;
+
+mod destructive;
+
+mod external;
+
+mod graph;
+
+mod impact;
+
+mod lexer;
+
+mod lint;
+pub use lint::LintReport;
+
+mod manifest;
+use manifest::MANIFEST_FILE_NAME;
+
+mod plan;
+use plan::{Plan, PLAN_VERSION};
+
+mod psql;
+
+mod template;
+
 mod repo;
-use repo::get_schema_script;
+pub use repo::{SchemaFiles, SchemaRepository};
+
+mod status;
+pub use status::Status;
+
+pub mod script;
+use script::SqlScript;
+
+mod submodules;
+
+use crate::repo::{
+    dependency_graph, get_schema_files, load_order, normalize_schema_path, read_schema_files,
+    split_range, working_tree_path, MergeOptions,
+};
+
+/// A migration between two revisions of a schema
+struct Migration {
+    /// Commit ids of the source and target schemas
+    from_commit: Option<String>,
+    to_commit: String,
+    script: String,
+    /// Migration from the target schema back to the source schema, if requested
+    down_script: Option<String>,
+    /// Schema at the target commit
+    target_schema: SqlScript,
+    /// Statements which would make the target database match the source schema, if it drifted from it
+    drift: Option<String>,
+}
+
+pub fn apply_diff(args: &DiffArgs, options: &PushOptions, config: &Config) -> Result<()> {
+    let started_at = SystemTime::now();
+    // dry runs change nothing, and need not wait for other pushes
+    let _lock = if options.dry_run {
+        None
+    } else {
+        Some(lock_target(&args.path, options.lock.wait, config)?)
+    };
+    let migration = get_migration(
+        args,
+        config,
+        &MigrationOptions {
+            infer_from: true,
+            check_drift: !options.ignore_drift,
+            down_script: !options.dry_run,
+        },
+    )?;
+
+    check_migration_drift(
+        &migration,
+        options.ignore_drift,
+        options.drift_report_only,
+        "push",
+        config,
+    )?;
+
+    check_destructive_changes(
+        &migration.script,
+        options.allow_destructive || options.dry_run,
+        config,
+    )?;
+
+    let target_tokio_config = config.target_session_config();
+    check_impact(&migration.script, options, &target_tokio_config)?;
+    if options.dry_run {
+        return dry_run(&migration.script, &target_tokio_config);
+    }
+
+    let script = SqlScript::new("migration", migration.script);
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).ok();
+    let schema_path = normalize_schema_path(&args.path);
+
+    let deployment = Deployment {
+        from_commit: migration.from_commit.as_deref(),
+        to_commit: &migration.to_commit,
+        schema_path: &schema_path,
+        script: &script.text,
+        down_script: migration.down_script.as_deref(),
+        engine: diff::engine_name(&config.diff_engine),
+        deployed_by: user.as_deref(),
+        started_at,
+    };
+    db::deploy(&deployment, &config.push, &target_tokio_config)
+        .map_err(|err| script.describe_error(err))?;
+
+    verify_push(&migration.to_commit, &migration.target_schema, config)
+}
+
+/// Fails if the target database drifted from the source schema of the migration, unless `--drift-report-only` is set
+fn check_migration_drift(
+    migration: &Migration,
+    ignore_drift: bool,
+    report_only: bool,
+    action: &str,
+    config: &Config,
+) -> Result<()> {
+    if let Some(drift) = &migration.drift {
+        let source = migration
+            .from_commit
+            .as_deref()
+            .unwrap_or("an empty schema");
+        report_drift(drift, source, action, report_only)?;
+    } else if migration.from_commit.is_none()
+        && !ignore_drift
+        && !db::is_empty(&config.target_session_config())?
+    {
+        let report = "The target database is not empty, but the source schema is empty: the migration would recreate existing objects";
+        if report_only {
+            eprintln!("warning: {}", report);
+        } else {
+            bail!(
+                "{}. Use --from <REV> to specify the revision it is at, or --ignore-drift to {} anyway",
+                report,
+                action
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fails with a report of the drift of the target database, unless `--drift-report-only` is set
+fn report_drift(drift: &str, source: &str, action: &str, report_only: bool) -> Result<()> {
+    let report = format!(
+        "The target database has drifted from the schema at {}. These statements would bring it back to that schema:\n\n{}\n",
+        source, drift
+    );
+    if report_only {
+        eprintln!("warning: {}", report);
+        Ok(())
+    } else {
+        bail!(
+            "{}\nUse --ignore-drift to {} anyway, or --drift-report-only to only report the drift",
+            report,
+            action
+        );
+    }
+}
+
+/// Prints the locks the script takes, and fails if it rewrites a table larger than `--max-rewrite-size`
+fn check_impact(
+    script: &str,
+    options: &PushOptions,
+    target_config: &tokio_postgres::Config,
+) -> Result<()> {
+    let impacts = impact::analyze(script);
+    let stats = db::table_stats(target_config, &impact::locked_tables(&impacts))?;
+    let description = impact::describe(&impacts, &stats);
+    if !description.is_empty() {
+        eprint!("Locks taken by the migration:\n{}", description);
+    }
+    if let Some(max_size) = options.max_rewrite_size {
+        impact::check_rewrite_size(&impacts, &stats, max_size)?;
+    }
+    Ok(())
+}
+
+pub fn rollback(args: &RollbackArgs, config: &Config) -> Result<()> {
+    let started_at = SystemTime::now();
+    let options = &args.options;
+    let target_tokio_config = config.target_session_config();
+    let _lock = if options.dry_run {
+        None
+    } else {
+        Some(lock_target(&args.path, options.lock.wait, config)?)
+    };
+    let schema_path = normalize_schema_path(&args.path);
+    let deployment = match db::last_deployment(&target_tokio_config, &schema_path)? {
+        Some(deployment) => deployment,
+        None => bail!(
+            "The target database has no deployment of {} to roll back",
+            schema_path
+        ),
+    };
+    if let Some(note) = unfinished_note(&deployment) {
+        bail!(
+            "Deployment #{} did not finish ({}), and cannot be rolled back",
+            deployment.id,
+            note
+        );
+    }
+    let down_script = match &deployment.down_script {
+        Some(down_script) => down_script,
+        None => bail!(
+            "Deployment #{} was recorded without a down migration, and cannot be rolled back",
+            deployment.id
+        ),
+    };
+    let from_commit = match &deployment.from_commit {
+        Some(from_commit) => from_commit,
+        None => bail!(
+            "Deployment #{} created the schema from an empty database, rolling it back would drop everything",
+            deployment.id
+        ),
+    };
+    eprintln!("Rolling back {}", describe_deployment(&deployment));
+
+    let repo = SchemaRepository::open(&args.repo_path)?;
+    let merge_options = MergeOptions::from_config(config);
+    if !options.ignore_drift {
+        let deployed_schema = repo.schema_script(
+            &deployment.to_commit,
+            &deployment.schema_path,
+            &merge_options,
+        )?;
+        let drift = diff_target_with_schema(&deployed_schema, config)?;
+        if !drift.trim().is_empty() {
+            report_drift(
+                &drift,
+                &deployment.to_commit,
+                "roll back",
+                options.drift_report_only,
+            )?;
+        }
+    }
+    let previous_schema =
+        repo.schema_script(from_commit, &deployment.schema_path, &merge_options)?;
+
+    let lost_changes = destructive::find_destructive_changes(&deployment.script);
+    if !lost_changes.is_empty() {
+        eprintln!("warning: the rollback recreates the objects the deployment removed or changed, but not their data:");
+        for change in &lost_changes {
+            eprintln!("  {}", change);
+        }
+    }
+    check_destructive_changes(
+        down_script,
+        options.allow_destructive || options.dry_run,
+        config,
+    )?;
+
+    check_impact(down_script, options, &target_tokio_config)?;
+    if options.dry_run {
+        return dry_run(down_script, &target_tokio_config);
+    }
+
+    let script = SqlScript::new("down migration", down_script.clone());
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).ok();
+    let rollback = Deployment {
+        from_commit: Some(&deployment.to_commit),
+        to_commit: from_commit,
+        schema_path: &deployment.schema_path,
+        script: &script.text,
+        down_script: Some(&deployment.script),
+        engine: &deployment.engine,
+        deployed_by: user.as_deref(),
+        started_at,
+    };
+    db::deploy(&rollback, &config.push, &target_tokio_config)
+        .map_err(|err| script.describe_error(err))?;
 
-use crate::repo::merge_sql_scripts;
+    verify_push(from_commit, &previous_schema, config)
+}
 
-pub fn apply_diff(args: &DiffArgs, config: &Config) -> Result<()> {
-    let diff_string = get_diff_string(args, config)?;
-    let target_tokio_config = config.target.to_tokio_postgres_config();
-    db::run_sql_script(&diff_string, &target_tokio_config)
+/// Prints the changes of the migration which may lose data, and fails if some of them are not allowed
+fn check_destructive_changes(script: &str, allow_destructive: bool, config: &Config) -> Result<()> {
+    let changes = destructive::find_destructive_changes(script);
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    eprintln!("The migration contains changes which may lose data:");
+    let mut blocked = 0;
+    for change in &changes {
+        if change.is_allowed(&config.push.allow_destructive) {
+            eprintln!("  {} (allowed by push.allow_destructive)", change);
+        } else {
+            blocked += 1;
+            eprintln!("  {}\n    {}", change, change.statement);
+        }
+    }
+
+    if blocked > 0 && !allow_destructive {
+        bail!(
+            "{} destructive change(s) were not applied. Use --allow-destructive to apply them, or add the objects to push.allow_destructive in postgit.toml",
+            blocked
+        );
+    }
+    Ok(())
+}
+
+fn dry_run(script: &str, config: &tokio_postgres::Config) -> Result<()> {
+    let results = db::dry_run(&lexer::split_statements(script), config)?;
+
+    for result in &results {
+        let status = match &result.outcome {
+            DryRunOutcome::Applied(rows) => format!("ok ({} rows)", rows),
+            DryRunOutcome::Failed(message) => format!("FAILED: {}", message),
+            DryRunOutcome::NotTransactional => {
+                "SKIPPED: cannot run inside a transaction".to_string()
+            }
+            DryRunOutcome::NotRun => "not run".to_string(),
+        };
+        println!(
+            "{}\n  {} in {:.1}ms\n",
+            result.statement,
+            status,
+            result.duration.as_secs_f64() * 1000.0
+        );
+    }
+
+    if results
+        .iter()
+        .any(|result| matches!(result.outcome, DryRunOutcome::Failed(_)))
+    {
+        bail!("The migration failed, the dry run was rolled back");
+    }
+    println!("Dry run successful, the migration was rolled back");
+    Ok(())
+}
+
+/// Compares the target database with the pushed schema, as the diff engine may not handle every kind of object
+fn verify_push(to: &str, target_schema: &SqlScript, config: &Config) -> Result<()> {
+    if config.push.verify == VerifyMode::Off {
+        return Ok(());
+    }
+
+    let residual = diff_target_with_schema(target_schema, config)?;
+    if residual.trim().is_empty() {
+        println!("Verified that the target database matches {}", to);
+        return Ok(());
+    }
+
+    let report = format!(
+        "The migration was applied, but the target database still differs from {}. These statements would reconcile it:\n\n{}",
+        to,
+        residual.trim_end()
+    );
+    match config.push.verify {
+        VerifyMode::Fail => bail!(report),
+        _ => {
+            eprintln!("warning: {}", report);
+            Ok(())
+        }
+    }
 }
 
 pub fn get_diff_string(args: &DiffArgs, config: &Config) -> Result<String> {
-    let source_path = match &args.source_path {
-        Some(path) => path,
-        None => &args.path,
+    Ok(get_migration(args, config, &MigrationOptions::default())?.script)
+}
+
+pub fn plan(args: &PlanArgs, config: &Config) -> Result<()> {
+    // the plan stands in for a push, and apply records its down migration
+    let migration = get_migration(
+        &args.diff,
+        config,
+        &MigrationOptions {
+            infer_from: true,
+            check_drift: !args.ignore_drift,
+            down_script: true,
+        },
+    )?;
+    check_migration_drift(
+        &migration,
+        args.ignore_drift,
+        args.drift_report_only,
+        "plan",
+        config,
+    )?;
+    let target_fingerprint = db::schema_fingerprint(&config.target_session_config())?;
+
+    let plan = Plan {
+        version: PLAN_VERSION,
+        from_ref: args.diff.from.clone(),
+        to_ref: args.diff.to.clone(),
+        from_commit: migration.from_commit,
+        to_commit: migration.to_commit,
+        schema_path: normalize_schema_path(&args.diff.path),
+        schema_hash: plan::sha256(&migration.target_schema.text),
+        engine: diff::engine_name(&config.diff_engine).to_string(),
+        target_fingerprint,
+        script: migration.script,
+        down_script: migration.down_script,
+    };
+    plan.write(&args.out)?;
+    eprintln!("Plan written to {}", args.out);
+    Ok(())
+}
+
+pub fn apply_plan(args: &ApplyArgs, config: &Config) -> Result<()> {
+    let started_at = SystemTime::now();
+    let plan = Plan::read(&args.plan)?;
+    let _lock = lock_target(&plan.schema_path, args.lock.wait, config)?;
+
+    let target_tokio_config = config.target_session_config();
+    let fingerprint = db::schema_fingerprint(&target_tokio_config)?;
+    if fingerprint != plan.target_fingerprint {
+        bail!(
+            "The schema of the target database changed since {} was created (fingerprint {}, expected {}). Create a new plan",
+            args.plan,
+            fingerprint,
+            plan.target_fingerprint
+        );
+    }
+
+    check_destructive_changes(&plan.script, args.allow_destructive, config)?;
+
+    let script = SqlScript::new("migration", plan.script.clone());
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).ok();
+    let deployment = Deployment {
+        from_commit: plan.from_commit.as_deref(),
+        to_commit: &plan.to_commit,
+        schema_path: &plan.schema_path,
+        script: &script.text,
+        down_script: plan.down_script.as_deref(),
+        engine: &plan.engine,
+        deployed_by: user.as_deref(),
+        started_at,
     };
+    db::deploy(&deployment, &config.push, &target_tokio_config)
+        .map_err(|err| script.describe_error(err))
+}
+
+/// Takes the lock preventing concurrent pushes of the schema at `path`, relative to the repository, to the target database
+fn lock_target(path: &str, wait: bool, config: &Config) -> Result<db::PushLock> {
+    db::lock_push(
+        db::push_lock_key(&config.target.database_id(), &normalize_schema_path(path)),
+        wait,
+        &config.target_session_config(),
+    )
+}
+
+/// What `get_migration` does besides diffing the schemas
+#[derive(Default)]
+struct MigrationOptions {
+    /// Uses the commit of the last deployment as source when `--from` is omitted, instead of an empty schema
+    infer_from: bool,
+    /// Compares the target database with the source schema
+    check_drift: bool,
+    /// Diffs the schemas the other way around, for the deployment record
+    down_script: bool,
+}
 
-    let source_schema_option = match &args.from {
-        Some(from) => Some(get_schema_script(&args.repo_path, from, source_path)?),
+fn get_migration(
+    args: &DiffArgs,
+    config: &Config,
+    options: &MigrationOptions,
+) -> Result<Migration> {
+    let merge_options = MergeOptions::from_config(config);
+    let repo = SchemaRepository::open(&args.repo_path)?;
+
+    let (from, to) = match split_range(&args.to) {
+        Some(_) if args.from.is_some() => bail!("--from cannot be used when --to is a range"),
+        Some((from, to)) => (Some(from), to),
+        None => (
+            resolve_from(args.from.as_deref(), &args.path, options.infer_from, config)?,
+            args.to.clone(),
+        ),
+    };
+
+    let rename = match (&args.source_path, &from) {
+        (None, Some(from)) => repo.detect_rename(from, &to, &args.path)?,
+        _ => None,
+    };
+    if let Some(rename) = &rename {
+        eprint!("{}", rename);
+    }
+    let source_path = match (&args.source_path, &rename) {
+        (Some(path), _) => path,
+        (None, Some(rename)) => &rename.source_path,
+        (None, None) => &args.path,
+    };
+
+    let source_schema_option = match &from {
+        Some(from) => Some(repo.schema_script(from, source_path, &merge_options)?),
         None => None,
     };
 
-    let target_schema = get_schema_script(&args.repo_path, &args.to, &args.path)?;
+    let target_options = MergeOptions {
+        renames: rename.map(|r| r.file_map()).unwrap_or_default(),
+        ..merge_options
+    };
+    let target_schema = repo.schema_script(&to, &args.path, &target_options)?;
 
     let diff_source_tokio_config = config.diff_engine.source.to_tokio_postgres_config();
     let diff_target_tokio_config = config.diff_engine.target.to_tokio_postgres_config();
@@ -55,43 +528,248 @@ pub fn get_diff_string(args: &DiffArgs, config: &Config) -> Result<String> {
     create_db(&diff_source_tokio_config)?;
     create_db(&diff_target_tokio_config)?;
     if let Some(source_schema) = source_schema_option {
-        run_sql_script(&source_schema, &diff_source_tokio_config)?;
+        run_sql_script(&source_schema.text, &diff_source_tokio_config)
+            .map_err(|err| source_schema.describe_error(err))?;
     }
 
-    run_sql_script(&target_schema, &diff_target_tokio_config)?;
+    run_sql_script(&target_schema.text, &diff_target_tokio_config)
+        .map_err(|err| target_schema.describe_error(err))?;
+
+    let diff = diff::run_diff_command(&config.diff_engine, &config.vars)?;
+    let down_script = if options.down_script {
+        let down_config = DiffEngineConfig {
+            command: config.diff_engine.command.clone(),
+            source: config.diff_engine.target.clone(),
+            target: config.diff_engine.source.clone(),
+        };
+        Some(diff::run_diff_command(&down_config, &config.vars)?)
+    } else {
+        None
+    };
 
-    let diff = diff::run_diff_command(&config.diff_engine)?;
+    // against an empty source schema, every object of the target would be reported as drift
+    let drift = if options.check_drift && from.is_some() {
+        Some(diff_target_with_source(config)?).filter(|d| !d.trim().is_empty())
+    } else {
+        None
+    };
 
     drop_db(&diff_source_tokio_config)?;
     drop_db(&diff_target_tokio_config)?;
 
-    Ok(diff)
+    Ok(Migration {
+        from_commit: match &from {
+            Some(from) => Some(repo.resolve_commit(from)?),
+            None => None,
+        },
+        to_commit: repo.resolve_commit(&to)?,
+        script: diff,
+        down_script,
+        target_schema,
+        drift,
+    })
 }
 
-pub fn deploy_changes(config: &Config, path: &Path, watch_config: &DiffEngineConfig) -> Result<()> {
-    let sql_extension = Some(OsStr::new("sql"));
+/// Returns the statements which would make the target database match the schema deployed in the diff source database
+fn diff_target_with_source(config: &Config) -> Result<String> {
+    // the deployments table is not part of the schema, but exists in target databases postgit pushed to
+    if db::has_deployments_table(&config.target_session_config())? {
+        db::create_deployments_table(&config.diff_engine.source.to_tokio_postgres_config())?;
+    }
+    let drift_config = DiffEngineConfig {
+        command: config.diff_engine.command.clone(),
+        source: config.target.clone(),
+        target: config.diff_engine.source.clone(),
+    };
+    diff::run_diff_command(&drift_config, &config.vars)
+}
 
-    print!("deploying changes ");
-    io::stdout().flush()?;
+/// Deploys the schema in the diff source database, and returns the statements which would make the target database match it
+fn diff_target_with_schema(schema: &SqlScript, config: &Config) -> Result<String> {
     let diff_source_tokio_config = config.diff_engine.source.to_tokio_postgres_config();
     drop_db(&diff_source_tokio_config)?;
     create_db(&diff_source_tokio_config)?;
+    run_sql_script(&schema.text, &diff_source_tokio_config)
+        .map_err(|err| schema.describe_error(err))?;
 
-    let file_entries = WalkDir::new(path)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.path().extension() == sql_extension)
-        .map(|e| (e.path().to_owned(), fs::read_to_string(e.path()).unwrap()))
-        .collect::<Vec<_>>();
+    let statements = diff_target_with_source(config)?;
+    drop_db(&diff_source_tokio_config)?;
+    Ok(statements)
+}
 
-    let sql_scripts = file_entries
+pub fn status(args: &StatusArgs, config: &Config) -> Result<Status> {
+    let files = get_schema_args_files(&args.schema)?;
+    let schema = files.merge(&MergeOptions::from_config(config))?;
+    let statements = diff_target_with_schema(&schema, config)?;
+
+    Ok(Status {
+        schema: args
+            .schema
+            .git_ref
+            .clone()
+            .unwrap_or_else(|| "working tree".to_string()),
+        path: args.schema.path.clone(),
+        statements,
+    })
+}
+
+/// Resolves the special `auto` and `empty` values of `--from`. An omitted `--from` means `auto` if `infer` is set, `empty` otherwise.
+/// `auto` uses the commit of the last deployment of the schema at `path` on the target, or an empty schema if the target is empty.
+fn resolve_from(
+    from: Option<&str>,
+    path: &str,
+    infer: bool,
+    config: &Config,
+) -> Result<Option<String>> {
+    match from {
+        Some("empty") => Ok(None),
+        None if !infer => Ok(None),
+        Some("auto") | None => {
+            let target_tokio_config = config.target_session_config();
+            let schema_path = normalize_schema_path(path);
+            match db::last_deployment(&target_tokio_config, &schema_path)? {
+                Some(deployment) if deployment.finished_at.is_none() => bail!(
+                    "The last deployment (#{}) did not finish: {} statement(s) of its script were committed. Bring the target database to a known revision, e.g. by running the rest of the script, and use --from <REV> to specify it",
+                    deployment.id,
+                    deployment.committed_statements.unwrap_or_default()
+                ),
+                Some(deployment) => {
+                    eprintln!(
+                        "Using the commit of the last deployment (#{}) as source: {}",
+                        deployment.id, deployment.to_commit
+                    );
+                    Ok(Some(deployment.to_commit))
+                }
+                None if db::is_empty(&target_tokio_config)? => Ok(None),
+                None => bail!(
+                    "The target database has no deployment history of {} but is not empty. Use --from <REV> to specify the revision it is at, or --from empty to recreate everything",
+                    schema_path
+                ),
+            }
+        }
+        Some(from) => Ok(Some(from.to_string())),
+    }
+}
+
+pub fn history(args: &HistoryArgs, config: &Config) -> Result<String> {
+    let deployments = db::get_deployments(
+        &config.target.to_tokio_postgres_config(),
+        args.limit,
+        args.show,
+    )?;
+
+    if let Some(id) = args.show {
+        return match deployments.first() {
+            Some(deployment) => Ok(format!(
+                "{}\nstarted at: {}\nfinished at: {}\nscript hash: {}\n\n{}{}",
+                describe_deployment(deployment),
+                deployment.started_at,
+                deployment.finished_at.as_deref().unwrap_or("-"),
+                deployment.script_hash,
+                deployment.script,
+                deployment
+                    .down_script
+                    .as_ref()
+                    .map_or(String::new(), |down| format!(
+                        "\n\n-- down migration\n{}",
+                        down
+                    ))
+            )),
+            None => bail!("Deployment {} not found", id),
+        };
+    }
+
+    if deployments.is_empty() {
+        return Ok("No deployments recorded".to_string());
+    }
+    Ok(deployments
         .iter()
-        .map(|e| (e.0.to_str().unwrap(), e.1.as_str()))
-        .collect::<HashMap<&str, &str>>();
+        .map(describe_deployment)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn describe_deployment(deployment: &DeploymentRecord) -> String {
+    let short = |sha: &str| sha.chars().take(7).collect::<String>();
+    format!(
+        "#{} {} {}..{} {} by {} with {}{}",
+        deployment.id,
+        deployment
+            .finished_at
+            .as_ref()
+            .unwrap_or(&deployment.started_at),
+        deployment
+            .from_commit
+            .as_deref()
+            .map_or("(empty)".to_string(), short),
+        short(&deployment.to_commit),
+        deployment.schema_path,
+        deployment.deployed_by,
+        deployment.engine,
+        unfinished_note(deployment).map_or(String::new(), |note| format!(" ({})", note))
+    )
+}
+
+/// Describes a deployment which failed after committing some of its statements
+fn unfinished_note(deployment: &DeploymentRecord) -> Option<String> {
+    if deployment.finished_at.is_some() {
+        return None;
+    }
+    Some(format!(
+        "unfinished, {} statement(s) committed",
+        deployment.committed_statements.unwrap_or_default()
+    ))
+}
 
-    let source_schema = merge_sql_scripts(&sql_scripts)?;
+fn get_schema_args_files(args: &SchemaArgs) -> Result<SchemaFiles> {
+    match &args.git_ref {
+        Some(git_ref) => get_schema_files(&args.repo_path, git_ref, &args.path),
+        None => read_schema_files(Path::new(&args.repo_path), Path::new(&args.path)),
+    }
+}
 
-    let source_deploy_result = run_sql_script(&source_schema, &diff_source_tokio_config);
+pub fn lint_imports(args: &SchemaArgs) -> Result<Vec<ImportLint>> {
+    let files = get_schema_args_files(args)?;
+    Ok(dependencies::lint_imports(&files.scripts()))
+}
+
+pub fn lint(args: &LintArgs, config: &Config) -> Result<LintReport> {
+    let migration = get_migration(&args.diff, config, &MigrationOptions::default())?;
+    lint::lint_migration(
+        &migration.script,
+        &migration.target_schema.files(),
+        &config.lint.rules,
+    )
+}
+
+pub fn graph(args: &GraphArgs, config: &Config) -> Result<String> {
+    let mut files = get_schema_args_files(&args.schema)?;
+    files.load_external_imports(&config.repositories)?;
+    let options = MergeOptions::from_config(config)
+        .with_schema_manifest(&files.root, files.manifest.as_deref())?;
+    let scripts = files.scripts();
+    let graph = dependency_graph(&scripts, &options)?;
+    let order = if args.order {
+        Some(load_order(&graph)?)
+    } else {
+        None
+    };
+    Ok(graph::render(&graph, order.as_deref(), args.format))
+}
+
+pub fn deploy_changes(config: &Config, path: &Path, watch_config: &DiffEngineConfig) -> Result<()> {
+    print!("deploying changes ");
+    io::stdout().flush()?;
+    let diff_source_tokio_config = config.diff_engine.source.to_tokio_postgres_config();
+    drop_db(&diff_source_tokio_config)?;
+    create_db(&diff_source_tokio_config)?;
+
+    let source_deploy_result = read_schema_files(Path::new(""), path)
+        .and_then(|files| files.merge(&MergeOptions::from_config(config)))
+        .and_then(|source_schema| {
+            run_sql_script(&source_schema.text, &diff_source_tokio_config)
+                .map_err(|err| source_schema.describe_error(err))
+        });
 
     match source_deploy_result {
         Err(err) => {
@@ -101,18 +779,38 @@ pub fn deploy_changes(config: &Config, path: &Path, watch_config: &DiffEngineCon
             Ok(())
         }
         Ok(_) => {
-            let mut diff_string = diff::run_diff_command(watch_config)?;
+            // pushes of the watched schema lock it by its path in the repository
+            let schema_path =
+                working_tree_path(path).unwrap_or_else(|| path.to_string_lossy().into_owned());
+            let lock = match lock_target(&schema_path, true, config) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    println!("❌");
+                    eprintln!("Could not lock the target db.\n{}", err);
+                    return Ok(());
+                }
+            };
+            let mut diff_string = diff::run_diff_command(watch_config, &config.vars)?;
 
-            let target_tokio_config = config.target.to_tokio_postgres_config();
+            let target_tokio_config = config.target_session_config();
             let apply_diff_result = run_sql_script(&diff_string, &target_tokio_config);
             if let Err(err) = apply_diff_result {
                 println!("❌");
                 eprintln!("Could not apply the changes to the target db.\n{}", err);
                 if config.watch.recreate_db_on_fail {
                     println!("Recreating target db");
+                    // the session holding the lock would prevent dropping the database
+                    drop(lock);
                     drop_db(&target_tokio_config)?;
                     create_db(&target_tokio_config)?;
-                    diff_string = diff::run_diff_command(watch_config)?;
+                    let _lock = match lock_target(&schema_path, true, config) {
+                        Ok(lock) => lock,
+                        Err(err) => {
+                            eprintln!("Could not lock the target db, retrying on the next file change.\n{}", err);
+                            return Ok(());
+                        }
+                    };
+                    diff_string = diff::run_diff_command(watch_config, &config.vars)?;
                     run_sql_script(&diff_string, &target_tokio_config).unwrap_or_else(|err| {
                         eprintln!("Failed again, retrying on the next file change.\n{}", err);
                     });
@@ -144,9 +842,10 @@ pub fn watch(args: &WatchArgs, config: &Config) -> Result<()> {
 
     // just print all events, this blocks forever
     for e in rx.into_iter().flatten() {
-        if e.iter()
-            .any(|event| event.path.extension() == sql_extension)
-        {
+        if e.iter().any(|event| {
+            event.path.extension() == sql_extension
+                || event.path.file_name() == Some(OsStr::new(MANIFEST_FILE_NAME))
+        }) {
             deploy_changes(config, path, &watch_config)?;
         }
     }
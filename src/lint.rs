@@ -0,0 +1,534 @@
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde_json::json;
+use sqlparser::ast::{
+    visit_expressions, AlterColumnOperation, AlterTableOperation, ColumnOption, Expr, Statement,
+    TableConstraint,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+use crate::cli::LintFormat;
+use crate::config::Severity;
+use crate::dependencies::{ident_value, object_key, ObjectReferences};
+use crate::lexer;
+
+pub struct Rule {
+    pub id: &'static str,
+    pub default_severity: Severity,
+    pub description: &'static str,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "add-column-volatile-default",
+        default_severity: Severity::Warning,
+        description: "Adding a column with a volatile default rewrites the table under an ACCESS EXCLUSIVE lock",
+    },
+    Rule {
+        id: "add-column-not-null-without-default",
+        default_severity: Severity::Error,
+        description: "Adding a not null column without a default fails if the table has rows",
+    },
+    Rule {
+        id: "create-index-non-concurrently",
+        default_severity: Severity::Warning,
+        description: "Creating an index without CONCURRENTLY blocks writes to the table until it is built",
+    },
+    Rule {
+        id: "alter-column-type",
+        default_severity: Severity::Warning,
+        description: "Changing the type of a column usually rewrites the table under an ACCESS EXCLUSIVE lock",
+    },
+    Rule {
+        id: "set-not-null",
+        default_severity: Severity::Warning,
+        description: "Setting a column not null scans the table under an ACCESS EXCLUSIVE lock, unless a validated check constraint proves it",
+    },
+    Rule {
+        id: "add-constraint-without-not-valid",
+        default_severity: Severity::Warning,
+        description: "Adding a check or foreign key constraint without NOT VALID scans the table while holding a lock",
+    },
+];
+
+/// Functions which make a column default volatile
+const VOLATILE_FUNCTIONS: &[&str] = &[
+    "random",
+    "clock_timestamp",
+    "timeofday",
+    "gen_random_uuid",
+    "uuid_generate_v1",
+    "uuid_generate_v1mc",
+    "uuid_generate_v4",
+    "nextval",
+];
+
+pub struct Finding {
+    pub rule: &'static Rule,
+    pub severity: Severity,
+    /// Schema-qualified name of the table the statement applies to
+    pub object: String,
+    pub statement: String,
+    /// Path and line of the statement defining the object in the target schema
+    pub location: Option<(String, usize)>,
+}
+
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+}
+
+/// Lints the statements of a migration.
+/// `files` are the files of the target schema, where findings are located and suppressed with a
+/// `-- postgit-lint: ignore <rule>, ...` comment before the statement defining or referencing the object.
+pub fn lint_migration(
+    script: &str,
+    files: &[(&str, &str)],
+    severities: &HashMap<String, Severity>,
+) -> Result<LintReport> {
+    for id in severities.keys() {
+        if !RULES.iter().any(|rule| rule.id == id) {
+            bail!("Unknown lint rule {} in postgit.toml", id);
+        }
+    }
+    let schema = SchemaIndex::build(files);
+    let mut not_null_checks = find_not_null_checks(script);
+    for (_, text) in files {
+        not_null_checks.extend(find_not_null_checks(text));
+    }
+
+    let mut findings = vec![];
+    for statement in lexer::split_statements(script) {
+        let text = lexer::strip_leading_comments(statement);
+        for (id, object) in check_statement(text, &not_null_checks) {
+            let rule = RULES.iter().find(|rule| rule.id == id).unwrap();
+            let severity = severities.get(id).copied().unwrap_or(rule.default_severity);
+            if severity == Severity::Off || schema.is_suppressed(id, &object) {
+                continue;
+            }
+            findings.push(Finding {
+                rule,
+                severity,
+                location: schema.definitions.get(&object).cloned(),
+                object,
+                statement: text.to_string(),
+            });
+        }
+    }
+    Ok(LintReport { findings })
+}
+
+/// Returns the rules the statement breaks, with the table they apply to.
+/// `not_null_checks` are the columns a validated check constraint proves not null.
+fn check_statement(
+    statement: &str,
+    not_null_checks: &HashSet<String>,
+) -> Vec<(&'static str, String)> {
+    let parsed = match Parser::parse_sql(&PostgreSqlDialect {}, statement) {
+        Ok(parsed) => parsed,
+        Err(_) => return vec![],
+    };
+
+    let mut broken = vec![];
+    for parsed in parsed {
+        match parsed {
+            Statement::CreateIndex(index) if !index.concurrently => broken.push((
+                "create-index-non-concurrently",
+                object_key(&index.table_name),
+            )),
+            Statement::AlterTable {
+                name, operations, ..
+            } => {
+                let table = object_key(&name);
+                for operation in operations {
+                    match operation {
+                        AlterTableOperation::AddColumn { column_def, .. } => {
+                            let default = column_def.options.iter().find_map(|o| match &o.option {
+                                ColumnOption::Default(expr) => Some(expr),
+                                _ => None,
+                            });
+                            let not_null = column_def
+                                .options
+                                .iter()
+                                .any(|o| matches!(o.option, ColumnOption::NotNull));
+                            match default {
+                                Some(expr) if is_volatile(expr) => {
+                                    broken.push(("add-column-volatile-default", table.clone()))
+                                }
+                                None if not_null => broken
+                                    .push(("add-column-not-null-without-default", table.clone())),
+                                _ => {}
+                            }
+                        }
+                        AlterTableOperation::AlterColumn { column_name, op } => match op {
+                            AlterColumnOperation::SetDataType { .. } => {
+                                broken.push(("alter-column-type", table.clone()))
+                            }
+                            AlterColumnOperation::SetNotNull
+                                if !not_null_checks.contains(&format!(
+                                    "{}.{}",
+                                    table,
+                                    ident_value(&column_name)
+                                )) =>
+                            {
+                                broken.push(("set-not-null", table.clone()))
+                            }
+                            _ => {}
+                        },
+                        // NOT VALID is not supported by the parser, so parsed constraints are validated
+                        AlterTableOperation::AddConstraint(
+                            TableConstraint::Check { .. } | TableConstraint::ForeignKey { .. },
+                        ) => broken.push(("add-constraint-without-not-valid", table.clone())),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    broken
+}
+
+/// Returns the columns which a `check (column is not null)` constraint of the script proves not null, as `schema.table.column`.
+/// Constraints added with NOT VALID are not parsed, so the ones found are validated.
+fn find_not_null_checks(script: &str) -> HashSet<String> {
+    let mut columns = HashSet::new();
+    for statement in lexer::split_statements(script) {
+        let text = lexer::strip_leading_comments(statement);
+        let Ok(parsed) = Parser::parse_sql(&PostgreSqlDialect {}, text) else {
+            continue;
+        };
+        for parsed in parsed {
+            let (table, checks): (String, Vec<&Expr>) = match &parsed {
+                Statement::CreateTable(create) => (
+                    object_key(&create.name),
+                    create
+                        .constraints
+                        .iter()
+                        .filter_map(|constraint| match constraint {
+                            TableConstraint::Check { expr, .. } => Some(expr.as_ref()),
+                            _ => None,
+                        })
+                        .chain(create.columns.iter().flat_map(|column| {
+                            column.options.iter().filter_map(|o| match &o.option {
+                                ColumnOption::Check(expr) => Some(expr),
+                                _ => None,
+                            })
+                        }))
+                        .collect(),
+                ),
+                Statement::AlterTable {
+                    name, operations, ..
+                } => (
+                    object_key(name),
+                    operations
+                        .iter()
+                        .filter_map(|operation| match operation {
+                            AlterTableOperation::AddConstraint(TableConstraint::Check {
+                                expr,
+                                ..
+                            }) => Some(expr.as_ref()),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                _ => continue,
+            };
+            for expr in checks {
+                if let Some(column) = not_null_column(expr) {
+                    columns.insert(format!("{}.{}", table, column));
+                }
+            }
+        }
+    }
+    columns
+}
+
+/// The column of a `column is not null` expression
+fn not_null_column(expr: &Expr) -> Option<String> {
+    match unnest(expr) {
+        Expr::IsNotNull(expr) => match unnest(expr) {
+            Expr::Identifier(column) => Some(ident_value(column)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn unnest(mut expr: &Expr) -> &Expr {
+    while let Expr::Nested(inner) = expr {
+        expr = inner;
+    }
+    expr
+}
+
+/// Whether the expression calls a volatile function, so that a default using it is evaluated for each row
+pub fn is_volatile(expr: &Expr) -> bool {
+    visit_expressions(expr, |expr| match expr {
+        Expr::Function(function)
+            if function
+                .name
+                .0
+                .last()
+                .is_some_and(|name| VOLATILE_FUNCTIONS.contains(&ident_value(name).as_str())) =>
+        {
+            ControlFlow::Break(())
+        }
+        _ => ControlFlow::Continue(()),
+    })
+    .is_break()
+}
+
+/// Where the objects of the target schema are defined, and which rules are suppressed for them
+struct SchemaIndex {
+    definitions: HashMap<String, (String, usize)>,
+    suppressions: Vec<(String, String)>,
+}
+
+impl SchemaIndex {
+    fn build(files: &[(&str, &str)]) -> SchemaIndex {
+        let suppression_regex = Regex::new(r"(?m)^\s*--\s*postgit-lint:\s*ignore\s+(.+)$").unwrap();
+
+        let mut index = SchemaIndex {
+            definitions: HashMap::new(),
+            suppressions: vec![],
+        };
+        for (path, text) in files {
+            for statement in lexer::split_statements(text) {
+                let offset = statement.as_ptr() as usize - text.as_ptr() as usize;
+                let code = lexer::strip_leading_comments(statement);
+                let line = text[..offset + statement.len() - code.len()]
+                    .matches('\n')
+                    .count()
+                    + 1;
+                let objects = ObjectReferences::analyze(code);
+                for object in &objects.defined {
+                    index
+                        .definitions
+                        .entry(object.clone())
+                        .or_insert_with(|| (path.to_string(), line));
+                }
+
+                let comments = &statement[..statement.len() - code.len()];
+                for group in suppression_regex.captures_iter(comments) {
+                    for rule in group[1].split(',').map(str::trim) {
+                        for object in objects.defined.iter().chain(&objects.referenced) {
+                            index.suppressions.push((rule.to_string(), object.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    fn is_suppressed(&self, rule: &str, object: &str) -> bool {
+        self.suppressions
+            .iter()
+            .any(|(r, o)| (r == rule || r == "all") && o == object)
+    }
+}
+
+impl LintReport {
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    pub fn render(&self, format: LintFormat) -> String {
+        match format {
+            LintFormat::Text => self.render_text(),
+            LintFormat::Sarif => serde_json::to_string_pretty(&self.sarif()).unwrap(),
+        }
+    }
+
+    fn render_text(&self) -> String {
+        if self.findings.is_empty() {
+            return "No findings".to_string();
+        }
+        self.findings
+            .iter()
+            .map(|finding| {
+                let location = match &finding.location {
+                    Some((path, line)) => format!("\n  --> {}:{}", path, line),
+                    None => String::new(),
+                };
+                format!(
+                    "{}[{}]: {} ({}){}\n  {}",
+                    finding.severity.name(),
+                    finding.rule.id,
+                    finding.rule.description,
+                    finding.object,
+                    location,
+                    finding.statement
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn sarif(&self) -> serde_json::Value {
+        let rules: Vec<_> = RULES
+            .iter()
+            .map(|rule| {
+                json!({
+                    "id": rule.id,
+                    "shortDescription": { "text": rule.description },
+                    "defaultConfiguration": { "level": rule.default_severity.name() }
+                })
+            })
+            .collect();
+        let results: Vec<_> = self
+            .findings
+            .iter()
+            .map(|finding| {
+                let mut result = json!({
+                    "ruleId": finding.rule.id,
+                    "level": finding.severity.name(),
+                    "message": {
+                        "text": format!("{} ({}): {}", finding.rule.description, finding.object, finding.statement)
+                    }
+                });
+                if let Some((path, line)) = &finding.location {
+                    result["locations"] = json!([{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": path },
+                            "region": { "startLine": line }
+                        }
+                    }]);
+                }
+                result
+            })
+            .collect();
+
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "postgit",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules
+                    }
+                },
+                "results": results
+            }]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATION: &str = r#"
+alter table "app"."user" add column "token" uuid default gen_random_uuid();
+alter table "app"."user" add column "created_at" timestamptz default now();
+alter table "app"."user" add column "age" int not null;
+CREATE INDEX user_email_idx ON app."user" USING btree (email);
+create index concurrently user_name_idx on app.user (name);
+alter table "app"."user" alter column "email" set data type varchar(64);
+alter table "app"."post" alter column "title" set not null;
+alter table "app"."post" add constraint "post_user_fkey" foreign key (user_id) references app.user (id);
+alter table "app"."post" add constraint "post_title_check" check (title <> '') not valid;
+"#;
+
+    fn rules(report: &LintReport) -> Vec<(&str, &str)> {
+        report
+            .findings
+            .iter()
+            .map(|finding| (finding.rule.id, finding.object.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn it_finds_unsafe_statements() {
+        let report = lint_migration(MIGRATION, &[], &HashMap::new()).unwrap();
+        assert_eq!(
+            vec![
+                ("add-column-volatile-default", "app.user"),
+                ("add-column-not-null-without-default", "app.user"),
+                ("create-index-non-concurrently", "app.user"),
+                ("alter-column-type", "app.user"),
+                ("set-not-null", "app.post"),
+                ("add-constraint-without-not-valid", "app.post"),
+            ],
+            rules(&report)
+        );
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn it_allows_setting_columns_proven_not_null() {
+        let migration = r#"
+alter table "app"."post" add constraint "post_title_not_null" check ((title is not null));
+alter table "app"."post" alter column "title" set not null;
+alter table "app"."post" alter column "body" set not null;
+alter table "app"."user" alter column "email" set not null;
+"#;
+        let files = [(
+            "user.sql",
+            "create table app.user (email text check (email is not null));",
+        )];
+
+        let report = lint_migration(migration, &files, &HashMap::new()).unwrap();
+        assert_eq!(
+            vec![
+                ("add-constraint-without-not-valid", "app.post"),
+                ("set-not-null", "app.post"),
+            ],
+            rules(&report)
+        );
+        assert!(report.findings[1].statement.contains("\"body\""));
+    }
+
+    #[test]
+    fn it_applies_severities_and_suppressions() {
+        let files = [
+            (
+                "schema/user.sql",
+                "create schema app;\n\n-- postgit-lint: ignore alter-column-type, create-index-non-concurrently\ncreate table app.user (id int, email text);\n",
+            ),
+            ("schema/post.sql", "create table app.post (title text);\n"),
+        ];
+        let severities = HashMap::from([
+            (
+                "add-column-not-null-without-default".to_string(),
+                Severity::Off,
+            ),
+            ("set-not-null".to_string(), Severity::Error),
+        ]);
+
+        let report = lint_migration(MIGRATION, &files, &severities).unwrap();
+        assert_eq!(
+            vec![
+                ("add-column-volatile-default", "app.user"),
+                ("set-not-null", "app.post"),
+                ("add-constraint-without-not-valid", "app.post"),
+            ],
+            rules(&report)
+        );
+        assert_eq!(
+            Some(("schema/user.sql".to_string(), 4)),
+            report.findings[0].location
+        );
+        assert_eq!(Severity::Error, report.findings[1].severity);
+
+        let sarif: serde_json::Value =
+            serde_json::from_str(&report.render(LintFormat::Sarif)).unwrap();
+        let result = &sarif["runs"][0]["results"][1];
+        assert_eq!("set-not-null", result["ruleId"]);
+        assert_eq!("error", result["level"]);
+        assert_eq!(
+            "schema/post.sql",
+            result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"]
+        );
+
+        let unknown = HashMap::from([("no-such-rule".to_string(), Severity::Off)]);
+        assert!(lint_migration(MIGRATION, &files, &unknown).is_err());
+    }
+}
@@ -4,7 +4,7 @@ use std::process;
 
 fn main() {
     let cli = Cli::parse();
-    let config = match Config::build() {
+    let mut config = match Config::build() {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Error while loading the config: {e}");
@@ -12,6 +12,13 @@ fn main() {
         }
     };
 
+    if let Some(target) = &cli.target {
+        if let Err(e) = config.use_target(target) {
+            eprintln!("Error while loading the config: {e}");
+            process::exit(1);
+        }
+    }
+
     match &cli.command {
         Commands::Diff(args) => match postgit::get_diff_string(args, &config) {
             Ok(diff_string) => {
@@ -23,7 +30,25 @@ fn main() {
             }
         },
         Commands::Push(args) => {
-            if let Err(e) = postgit::apply_diff(args, &config) {
+            if let Err(e) = postgit::apply_diff(&args.diff, &args.options, &config) {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Plan(args) => {
+            if let Err(e) = postgit::plan(args, &config) {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Apply(args) => {
+            if let Err(e) = postgit::apply_plan(args, &config) {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Rollback(args) => {
+            if let Err(e) = postgit::rollback(args, &config) {
                 eprintln!("Application error: {e}");
                 process::exit(1);
             }
@@ -34,5 +59,61 @@ fn main() {
                 process::exit(1);
             }
         }
+        Commands::LintImports(args) => match postgit::lint_imports(args) {
+            Ok(lints) => {
+                for lint in &lints {
+                    println!("{lint}");
+                }
+                if !lints.is_empty() {
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        },
+        Commands::Lint(args) => match postgit::lint(args, &config) {
+            Ok(report) => {
+                println!("{}", report.render(args.format));
+                if report.has_errors() {
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                process::exit(2);
+            }
+        },
+        Commands::Graph(args) => match postgit::graph(args, &config) {
+            Ok(graph) => {
+                println!("{graph}");
+            }
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        },
+        Commands::History(args) => match postgit::history(args, &config) {
+            Ok(history) => {
+                println!("{history}");
+            }
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        },
+        Commands::Status(args) => match postgit::status(args, &config) {
+            Ok(status) => {
+                println!("{}", status.render(args.format));
+                if status.has_drift() {
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                process::exit(2);
+            }
+        },
     }
 }
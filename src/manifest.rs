@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE_NAME: &str = "postgit.manifest";
+
+/// An explicit list of the files making up a schema, in load order.
+/// Entries are file paths or glob patterns, relative to the manifest root.
+#[derive(Deserialize, PartialEq, Eq, Debug, Default, Clone)]
+pub struct Manifest {
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Files only loaded for a given target, after the common files
+    #[serde(default)]
+    pub targets: HashMap<String, ManifestTarget>,
+    /// Directory the entries are relative to
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Default, Clone)]
+pub struct ManifestTarget {
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+/// The result of matching the manifest entries against the files of a schema
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResolvedManifest<'a> {
+    /// Files listed for the current target, in load order
+    pub listed: Vec<&'a str>,
+    /// Files only listed for other targets
+    pub other_targets: HashSet<&'a str>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Manifest> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.targets.is_empty()
+    }
+
+    pub fn with_root(self, root: &Path) -> Manifest {
+        Manifest {
+            root: root.to_owned(),
+            ..self
+        }
+    }
+
+    pub fn resolve<'a>(
+        &self,
+        paths: &[&'a str],
+        target: Option<&str>,
+    ) -> Result<ResolvedManifest<'a>> {
+        let mut sorted_paths = paths.to_vec();
+        sorted_paths.sort();
+
+        let target_files = target
+            .and_then(|t| self.targets.get(t))
+            .map(|t| t.files.as_slice())
+            .unwrap_or_default();
+
+        let mut listed: Vec<&str> = vec![];
+        for entry in self.files.iter().chain(target_files) {
+            let matches = self.matching_paths(entry, &sorted_paths)?;
+            if matches.is_empty() {
+                if is_literal(entry) {
+                    bail!("File {} listed in the manifest does not exist", entry);
+                }
+                eprintln!("warning: manifest entry {} does not match any file", entry);
+            }
+            for path in matches {
+                if !listed.contains(&path) {
+                    listed.push(path);
+                }
+            }
+        }
+
+        let mut other_targets = HashSet::new();
+        for (name, other) in &self.targets {
+            if Some(name.as_str()) == target {
+                continue;
+            }
+            for entry in &other.files {
+                other_targets.extend(
+                    self.matching_paths(entry, &sorted_paths)?
+                        .into_iter()
+                        .filter(|path| !listed.contains(path)),
+                );
+            }
+        }
+
+        Ok(ResolvedManifest {
+            listed,
+            other_targets,
+        })
+    }
+
+    fn matching_paths<'a>(&self, entry: &str, sorted_paths: &[&'a str]) -> Result<Vec<&'a str>> {
+        let pattern = Pattern::new(entry.strip_prefix("./").unwrap_or(entry))?;
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::default()
+        };
+
+        Ok(sorted_paths
+            .iter()
+            .filter(|path| {
+                Path::new(path)
+                    .strip_prefix(&self.root)
+                    .is_ok_and(|relative| pattern.matches_path_with(relative, options))
+            })
+            .copied()
+            .collect())
+    }
+}
+
+fn is_literal(entry: &str) -> bool {
+    !entry.contains(['*', '?', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_resolves_entries_in_order() {
+        let manifest = Manifest::parse(
+            r#"
+            files = ["schema.sql", "types/*.sql", "tables/**/*.sql"]
+
+            [targets.prod]
+            files = ["grants/prod.sql"]
+
+            [targets.dev]
+            files = ["grants/dev.sql", "seed/*.sql"]
+            "#,
+        )
+        .unwrap()
+        .with_root(Path::new("db"));
+
+        let paths = [
+            "db/tables/user/user.sql",
+            "db/tables/todo.sql",
+            "db/types/status.sql",
+            "db/schema.sql",
+            "db/grants/prod.sql",
+            "db/grants/dev.sql",
+            "db/seed/users.sql",
+        ];
+
+        let resolved = manifest.resolve(&paths, Some("prod")).unwrap();
+
+        assert_eq!(
+            vec![
+                "db/schema.sql",
+                "db/types/status.sql",
+                "db/tables/todo.sql",
+                "db/tables/user/user.sql",
+                "db/grants/prod.sql"
+            ],
+            resolved.listed
+        );
+        assert_eq!(
+            HashSet::from(["db/grants/dev.sql", "db/seed/users.sql"]),
+            resolved.other_targets
+        );
+    }
+
+    #[test]
+    fn it_rejects_missing_files() {
+        let manifest = Manifest::parse(r#"files = ["schema.sql", "missing.sql"]"#).unwrap();
+
+        let err = manifest.resolve(&["schema.sql"], None).unwrap_err();
+
+        assert_eq!(
+            "File missing.sql listed in the manifest does not exist",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+
+pub const PLAN_VERSION: u32 = 1;
+
+/// A migration written by the `plan` command, to be reviewed and run with the `apply` command
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Plan {
+    pub version: u32,
+    /// Revisions as given on the command line
+    pub from_ref: Option<String>,
+    pub to_ref: String,
+    pub from_commit: Option<String>,
+    pub to_commit: String,
+    pub schema_path: String,
+    /// SHA-256 hash of the merged target schema
+    pub schema_hash: String,
+    pub engine: String,
+    /// Fingerprint of the schema of the target database when the plan was created
+    pub target_fingerprint: String,
+    pub script: String,
+    /// Script reverting the migration
+    #[serde(default)]
+    pub down_script: Option<String>,
+}
+
+impl Plan {
+    pub fn read(path: &str) -> Result<Plan> {
+        let text = fs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+        let plan: Plan = serde_json::from_str(&text)
+            .with_context(|| format!("{} is not a valid plan file", path))?;
+        if plan.version != PLAN_VERSION {
+            bail!(
+                "{} uses version {} of the plan format, which is not supported",
+                path,
+                plan.version
+            );
+        }
+        Ok(plan)
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Could not write {}", path))
+    }
+}
+
+pub fn sha256(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn it_reads_written_plans() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("plan.json");
+        let path = path.to_str().unwrap();
+        let plan = Plan {
+            version: PLAN_VERSION,
+            from_ref: None,
+            to_ref: "main".to_string(),
+            from_commit: None,
+            to_commit: "0123456789abcdef".to_string(),
+            schema_path: "schema".to_string(),
+            schema_hash: sha256("create schema app;"),
+            engine: "migra".to_string(),
+            target_fingerprint: sha256(""),
+            script: "create schema app;".to_string(),
+            down_script: Some("drop schema app;".to_string()),
+        };
+        plan.write(path).unwrap();
+
+        assert_eq!(plan, Plan::read(path).unwrap());
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            plan.target_fingerprint
+        );
+
+        fs::write(path, r#"{"version": 2}"#).unwrap();
+        assert!(Plan::read(path).is_err());
+    }
+}
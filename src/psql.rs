@@ -0,0 +1,297 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+use crate::lexer;
+
+// Meta-commands that only affect psql's own output and can safely be dropped
+const IGNORED_META_COMMANDS: [&str; 13] = [
+    "echo",
+    "qecho",
+    "warn",
+    "timing",
+    "pset",
+    "x",
+    "a",
+    "t",
+    "H",
+    "f",
+    "C",
+    "T",
+    "errverbose",
+];
+
+const INCLUDE_META_COMMANDS: [&str; 4] = ["i", "ir", "include", "include_relative"];
+
+/// A `\i` or `\ir` meta-command found in a script
+#[derive(Debug, PartialEq, Eq)]
+pub struct Include {
+    pub path: String,
+    pub relative: bool,
+}
+
+/// Returns the files included with `\i`, `\include`, `\ir` or `\include_relative`
+pub fn find_includes(script: &str) -> Vec<Include> {
+    script
+        .lines()
+        .filter_map(|line| {
+            let (command, args) = split_meta_command(line.trim_start().strip_prefix('\\')?);
+            if !INCLUDE_META_COMMANDS.contains(&command) {
+                return None;
+            }
+            let path = parse_args(args).ok()?.into_iter().next()?;
+            Some(Include {
+                path,
+                relative: command == "ir" || command == "include_relative",
+            })
+        })
+        .collect()
+}
+
+/// Expands psql variables and removes the supported meta-commands from a script, so that it can be sent to the server.
+/// `vars` holds the psql variables, which persist across the scripts of a schema, in load order.
+/// Meta-commands are replaced with empty lines to preserve line numbers.
+pub fn preprocess(path: &str, script: &str, vars: &mut HashMap<String, String>) -> Result<String> {
+    let mut output = String::with_capacity(script.len());
+    let mut chars = script.char_indices().peekable();
+    let mut line = 1;
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\n' => {
+                line += 1;
+                output.push(c);
+            }
+            _ if lexer::literal_end(script, i).is_some() => {
+                let end = lexer::literal_end(script, i).unwrap();
+                line += script[i..end].matches('\n').count();
+                output.push_str(&script[i..end]);
+                lexer::skip_to(&mut chars, end);
+            }
+            ':' if script[i..].starts_with("::") => {
+                output.push_str("::");
+                chars.next();
+            }
+            ':' => match interpolate(&script[i + 1..], vars) {
+                Some((value, len)) => {
+                    output.push_str(&value);
+                    lexer::skip_to(&mut chars, i + 1 + len);
+                }
+                None => output.push(c),
+            },
+            '\\' => {
+                let end = script[i..].find('\n').map_or(script.len(), |n| i + n);
+                let (command, args) = split_meta_command(&script[i + 1..end]);
+                run_meta_command(command, args, vars)
+                    .map_err(|err| anyhow::anyhow!("{}:{}: {}", path, line, err))?;
+                lexer::skip_to(&mut chars, end);
+            }
+            _ => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+fn run_meta_command(command: &str, args: &str, vars: &mut HashMap<String, String>) -> Result<()> {
+    if INCLUDE_META_COMMANDS.contains(&command) || IGNORED_META_COMMANDS.contains(&command) {
+        return Ok(());
+    }
+
+    match command {
+        "set" => {
+            let args = parse_args(args)?;
+            match args.split_first() {
+                Some((name, values)) => {
+                    vars.insert(name.to_owned(), values.concat());
+                }
+                None => bail!("\\set without arguments is not supported"),
+            }
+        }
+        "unset" => {
+            for name in parse_args(args)? {
+                vars.remove(&name);
+            }
+        }
+        _ => bail!("unsupported psql meta-command \\{}", command),
+    }
+    Ok(())
+}
+
+/// Splits a meta-command line (without the leading backslash) into the command name and its arguments
+fn split_meta_command(line: &str) -> (&str, &str) {
+    let name_len = line
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(line.len());
+    // commands such as `\!` or `\?` are made of a single symbol
+    let name_len = if name_len == 0 {
+        line.chars().next().map_or(0, char::len_utf8)
+    } else {
+        name_len
+    };
+    (&line[..name_len], line[name_len..].trim())
+}
+
+/// Parses meta-command arguments: whitespace separated words, optionally single-quoted
+fn parse_args(args: &str) -> Result<Vec<String>> {
+    let mut parsed = vec![];
+    let mut chars = args.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut arg = String::new();
+        if c == '\'' {
+            loop {
+                match chars.next() {
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        arg.push('\'');
+                    }
+                    Some('\'') => break,
+                    Some(c) => arg.push(c),
+                    None => bail!("unterminated quoted string in meta-command arguments"),
+                }
+            }
+        } else if c == '`' {
+            bail!("backquoted shell commands are not supported in meta-command arguments");
+        } else {
+            arg.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        parsed.push(arg);
+    }
+
+    Ok(parsed)
+}
+
+/// Expands `:name`, `:'name'` or `:"name"` at the beginning of `s` (right after the colon).
+/// Returns the expanded value and the length of the consumed text, or `None` if the text is not a defined variable.
+fn interpolate(s: &str, vars: &HashMap<String, String>) -> Option<(String, usize)> {
+    let quote = s.chars().next().filter(|c| *c == '\'' || *c == '"');
+    let name_start = quote.map_or(0, |_| 1);
+    let name_len = s[name_start..]
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(s.len() - name_start);
+    if name_len == 0 {
+        return None;
+    }
+    let name = &s[name_start..name_start + name_len];
+
+    match quote {
+        Some(q) => {
+            if !s[name_start + name_len..].starts_with(q) {
+                return None;
+            }
+            let value = vars.get(name)?;
+            let quoted = if q == '\'' {
+                quote_literal(value)
+            } else {
+                quote_identifier(value)
+            };
+            Some((quoted, name_len + 2))
+        }
+        None => Some((vars.get(name)?.clone(), name_len)),
+    }
+}
+
+pub fn quote_literal(value: &str) -> String {
+    if value.contains('\\') {
+        format!("E'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+pub fn quote_identifier(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_includes() {
+        let script = r#"\i schema/a.sql
+\ir ../b.sql
+\include_relative 'c d.sql'
+-- \i commented.sql
+select 1;"#;
+
+        assert_eq!(
+            vec![
+                Include {
+                    path: "schema/a.sql".to_string(),
+                    relative: false
+                },
+                Include {
+                    path: "../b.sql".to_string(),
+                    relative: true
+                },
+                Include {
+                    path: "c d.sql".to_string(),
+                    relative: true
+                },
+            ],
+            find_includes(script)
+        );
+    }
+
+    #[test]
+    fn it_expands_variables() {
+        let mut vars = HashMap::new();
+        let script = r#"\set role app_user
+\set comment 'it''s'
+grant usage on schema app to :role;
+comment on schema app is :'comment';
+create role :"role";
+select 1::int, ':role', $$ :role $$;"#;
+
+        let output = preprocess("a.sql", script, &mut vars).unwrap();
+
+        assert_eq!(
+            r#"
+
+grant usage on schema app to app_user;
+comment on schema app is 'it''s';
+create role "app_user";
+select 1::int, ':role', $$ :role $$;"#,
+            output
+        );
+    }
+
+    #[test]
+    fn it_keeps_variables_across_scripts() {
+        let mut vars = HashMap::new();
+        preprocess("a.sql", r"\set schema_name app", &mut vars).unwrap();
+        let output = preprocess("b.sql", "create schema :schema_name;", &mut vars).unwrap();
+
+        assert_eq!("create schema app;", output);
+    }
+
+    #[test]
+    fn it_strips_harmless_meta_commands() {
+        let mut vars = HashMap::new();
+        let script = "\\echo creating tables\n\\i other.sql\ncreate table a();\n";
+
+        let output = preprocess("a.sql", script, &mut vars).unwrap();
+
+        assert_eq!("\n\ncreate table a();\n", output);
+    }
+
+    #[test]
+    fn it_rejects_unsupported_meta_commands() {
+        let mut vars = HashMap::new();
+        let script = "create table a();\n\n\\copy a from 'a.csv'\n";
+
+        let err = preprocess("schema/a.sql", script, &mut vars).unwrap_err();
+
+        assert_eq!(
+            "schema/a.sql:3: unsupported psql meta-command \\copy",
+            err.to_string()
+        );
+    }
+}
@@ -1,77 +1,762 @@
 use anyhow::{bail, Result};
 use git_repository::bstr::ByteSlice;
 use git_repository::objs::tree::EntryMode;
+use git_repository::objs::Kind;
 use git_repository::traverse::tree::Recorder;
-use git_repository::{Commit, Object, Repository};
-use petgraph::algo::toposort;
+use git_repository::{Commit, ObjectId, Repository};
+use petgraph::algo::has_path_connecting;
 use petgraph::prelude::DiGraphMap;
 use petgraph::Direction;
 use regex::Regex;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
 use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
 
-pub fn get_schema_script(repo_path: &str, ref_or_sha1: &str, schema_path: &str) -> Result<String> {
-    let repo_path = Path::new(repo_path);
-    let mut schema_path = Path::new(schema_path);
-    if let Ok(p) = schema_path.strip_prefix("./") {
-        schema_path = p
+use crate::config::{Config, RepositoryConfig};
+use crate::dependencies;
+use crate::external;
+use crate::manifest::{Manifest, ResolvedManifest, MANIFEST_FILE_NAME};
+use crate::psql;
+use crate::script::SqlScript;
+use crate::submodules::{self, Submodule, GITMODULES_FILE_NAME};
+use crate::template;
+
+const OBJECT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+/// Minimum similarity for files with different contents to be considered renamed, as git does
+const RENAME_SIMILARITY: f64 = 0.5;
+/// Maximum number of files compared by rename detection, which reads both files of each comparison
+const MAX_RENAME_COMPARISONS: usize = 1000;
+
+/// Options controlling how the files of a schema are merged into a single script
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    /// Variables expanded in `{{ name }}` placeholders, also available as psql variables
+    pub vars: HashMap<String, String>,
+    /// Explicit load order, replacing the lexicographic order
+    pub manifest: Option<Manifest>,
+    /// Name of the selected target, used to pick the target-specific manifest entries
+    pub target: Option<String>,
+    /// Whether to order the files based on the objects they define and reference, in addition to imports
+    pub infer_dependencies: bool,
+    /// Renamed files, used to resolve imports of their previous path
+    pub renames: HashMap<String, String>,
+    /// Repositories files can be imported from
+    pub repositories: HashMap<String, RepositoryConfig>,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            vars: HashMap::new(),
+            manifest: None,
+            target: None,
+            infer_dependencies: true,
+            renames: HashMap::new(),
+            repositories: HashMap::new(),
+        }
     }
+}
 
-    let repo = git_repository::open(repo_path)?;
-    let commit_option = try_find_commit(&repo, ref_or_sha1)?;
+impl MergeOptions {
+    pub fn from_config(config: &Config) -> MergeOptions {
+        MergeOptions {
+            vars: config.vars.clone(),
+            manifest: Some(config.schema.manifest.clone()).filter(|m| !m.is_empty()),
+            target: config.target_name.clone(),
+            infer_dependencies: config.schema.infer_dependencies,
+            renames: HashMap::new(),
+            repositories: config.repositories.clone(),
+        }
+    }
 
-    if let Some(commit) = commit_option {
-        let tree = commit.tree()?;
+    /// Uses the manifest found at the root of the schema, if any, or the one from the config
+    pub fn with_schema_manifest(
+        &self,
+        root: &Path,
+        manifest: Option<&str>,
+    ) -> Result<MergeOptions> {
+        let manifest = match manifest {
+            Some(text) => Some(Manifest::parse(text)?),
+            None => self.manifest.clone(),
+        };
 
-        let mut recorder = Recorder::default();
+        Ok(MergeOptions {
+            manifest: manifest.map(|m| m.with_root(root)),
+            ..self.clone()
+        })
+    }
+}
 
-        tree.traverse().breadthfirst::<Recorder>(&mut recorder)?;
+/// The files making up a schema, keyed by path
+#[derive(Debug, Default)]
+pub struct SchemaFiles {
+    /// Directory or file the schema was loaded from
+    pub root: PathBuf,
+    pub scripts: HashMap<String, String>,
+    /// Content of the manifest found at the root of the schema, if any
+    pub manifest: Option<String>,
+}
+
+impl SchemaFiles {
+    pub fn scripts(&self) -> HashMap<&str, &str> {
+        self.scripts
+            .iter()
+            .map(|(path, script)| (path.as_str(), script.as_str()))
+            .collect()
+    }
 
-        let objects_with_path = recorder
-            .records
+    /// Adds the files imported from other repositories
+    pub fn load_external_imports(
+        &mut self,
+        repositories: &HashMap<String, RepositoryConfig>,
+    ) -> Result<()> {
+        external::load_external_imports(&mut self.scripts, repositories)
+    }
+
+    pub fn merge(&self, options: &MergeOptions) -> Result<SqlScript> {
+        let options = options.with_schema_manifest(&self.root, self.manifest.as_deref())?;
+        let mut scripts = self.scripts.clone();
+        external::load_external_imports(&mut scripts, &options.repositories)?;
+        let sql_scripts = scripts
             .iter()
-            .filter(|entry| {
-                matches!(entry.mode, EntryMode::Blob)
-                    && entry.filepath.to_path().unwrap().starts_with(schema_path)
-            })
-            .filter_map(|entry| match repo.find_object(entry.oid) {
-                Ok(object) => Some((entry.filepath.to_str().unwrap(), object)),
+            .map(|(path, script)| (path.as_str(), script.as_str()))
+            .collect();
+        merge_sql_scripts(&sql_scripts, &options)
+    }
+}
+
+/// A git repository the schemas are read from.
+/// Opening it once allows reading several revisions while sharing the object cache.
+pub struct SchemaRepository {
+    repo: Repository,
+    /// Repositories of the submodules traversed so far, to read their blobs
+    submodules: RefCell<Vec<Repository>>,
+}
+
+impl SchemaRepository {
+    pub fn open(repo_path: &str) -> Result<SchemaRepository> {
+        let mut repo = git_repository::open(repo_path)?;
+        repo.object_cache_size_if_unset(OBJECT_CACHE_SIZE);
+        Ok(SchemaRepository {
+            repo,
+            submodules: RefCell::new(vec![]),
+        })
+    }
+
+    pub fn schema_script(
+        &self,
+        ref_or_sha1: &str,
+        schema_path: &str,
+        options: &MergeOptions,
+    ) -> Result<SqlScript> {
+        self.schema_files(ref_or_sha1, schema_path)?.merge(options)
+    }
+
+    /// Returns the id of the commit a revision resolves to
+    pub fn resolve_commit(&self, ref_or_sha1: &str) -> Result<String> {
+        match try_find_commit(&self.repo, ref_or_sha1)? {
+            Some(commit) => Ok(commit.id().to_string()),
+            None => bail!("Didn't find source commit for ref {}", ref_or_sha1),
+        }
+    }
+
+    /// Reads the files of a schema at the given revision. Only the subtree of the schema path is traversed.
+    pub fn schema_files(&self, ref_or_sha1: &str, schema_path: &str) -> Result<SchemaFiles> {
+        let schema_path = trim_schema_path(schema_path);
+
+        let mut scripts = HashMap::new();
+        for (path, oid) in self.blob_ids(ref_or_sha1, schema_path)? {
+            match self.blob_data(oid) {
+                Ok(data) => {
+                    scripts.insert(path, data.to_str()?.to_string());
+                }
                 Err(err) => {
-                    eprintln!("Could not find object with id {}:/n{}", entry.oid, err);
-                    None
+                    eprintln!("Could not find object with id {}:/n{}", oid, err);
                 }
-            })
-            .collect::<Vec<(&str, Object)>>();
+            }
+        }
+
+        let manifest_path = schema_path.join(MANIFEST_FILE_NAME);
+        let manifest = scripts.remove(manifest_path.to_str().unwrap());
+
+        Ok(SchemaFiles {
+            root: schema_path.to_owned(),
+            scripts,
+            manifest,
+        })
+    }
+
+    /// Detects whether the schema, or some of its files, were renamed between two revisions.
+    /// Files are matched on identical content first, then on similar content.
+    pub fn detect_rename(
+        &self,
+        from: &str,
+        to: &str,
+        schema_path: &str,
+    ) -> Result<Option<SchemaRename>> {
+        let schema_path = trim_schema_path(schema_path);
+        let target_blobs = self.blob_ids(to, schema_path)?;
+        let source_blobs = self.blob_ids(from, schema_path)?;
+        let moved = source_blobs.is_empty();
 
-        if objects_with_path.len() == 1 {
-            let script = objects_with_path[0].1.data.to_str()?.to_string();
-            Ok(script)
+        let candidates: Vec<(String, ObjectId)> = if moved {
+            // the schema may have been anywhere in the tree, in files with the same extensions
+            let extensions: HashSet<Option<&OsStr>> = target_blobs
+                .iter()
+                .map(|(path, _)| Path::new(path).extension())
+                .collect();
+            let target_paths: HashSet<String> = self
+                .blob_ids(to, Path::new(""))?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            self.blob_ids(from, Path::new(""))?
+                .into_iter()
+                .filter(|(path, _)| {
+                    extensions.contains(&Path::new(path).extension())
+                        && !target_paths.contains(path)
+                })
+                .collect()
         } else {
-            let scripts = objects_with_path
+            let target_paths: HashSet<&str> =
+                target_blobs.iter().map(|(path, _)| path.as_str()).collect();
+            source_blobs
                 .iter()
-                .map(|(path, object)| (*path, object.data.to_str().unwrap()))
-                .collect::<HashMap<&str, &str>>();
+                .filter(|(path, _)| !target_paths.contains(path.as_str()))
+                .cloned()
+                .collect()
+        };
+        let source_paths: HashSet<&str> =
+            source_blobs.iter().map(|(path, _)| path.as_str()).collect();
+
+        let mut used = HashSet::new();
+        let mut files = vec![];
+        let mut comparisons = 0;
+        for (path, oid) in &target_blobs {
+            if source_paths.contains(path.as_str()) {
+                continue;
+            }
+            // blank files say nothing about where the schema used to be
+            if significant_lines(&self.blob_data(*oid)?.to_str_lossy()).is_empty() {
+                continue;
+            }
+            let exact = candidates
+                .iter()
+                .filter(|(candidate, id)| id == oid && !used.contains(candidate))
+                .min_by_key(|(candidate, _)| (file_name(candidate) != file_name(path), candidate));
+            let source = match exact {
+                Some((candidate, _)) => Some(candidate.clone()),
+                None => self.most_similar(path, *oid, &candidates, &used, &mut comparisons)?,
+            };
+            if let Some(source) = source {
+                used.insert(source.clone());
+                files.push((source, path.clone()));
+            }
+        }
+        files.sort();
+        if comparisons > MAX_RENAME_COMPARISONS {
+            eprintln!(
+                "warning: rename detection stopped after comparing {} files, use --source-path to set the source schema path",
+                MAX_RENAME_COMPARISONS
+            );
+        }
+
+        let source_path = if moved {
+            // a new schema may have a few files resembling unrelated deleted ones
+            if files.len() * 2 <= target_blobs.len() {
+                return Ok(None);
+            }
+            match source_root(schema_path, &files) {
+                Some(root) => root,
+                None => return Ok(None),
+            }
+        } else if files.is_empty() {
+            return Ok(None);
+        } else {
+            schema_path.to_str().unwrap().to_string()
+        };
+
+        Ok(Some(SchemaRename {
+            source_path,
+            target_path: schema_path.to_str().unwrap().to_string(),
+            files,
+        }))
+    }
+
+    /// Returns the candidate with the most similar content, if it is at least 50% similar.
+    /// `comparisons` counts the comparisons made so far, which stop after `MAX_RENAME_COMPARISONS`.
+    fn most_similar(
+        &self,
+        path: &str,
+        oid: ObjectId,
+        candidates: &[(String, ObjectId)],
+        used: &HashSet<String>,
+        comparisons: &mut usize,
+    ) -> Result<Option<String>> {
+        let content = self.blob_data(oid)?;
+        let mut best: Option<(f64, &str)> = None;
+        for (candidate, candidate_oid) in candidates {
+            if used.contains(candidate)
+                || Path::new(candidate).extension() != Path::new(path).extension()
+            {
+                continue;
+            }
+            *comparisons += 1;
+            if *comparisons > MAX_RENAME_COMPARISONS {
+                break;
+            }
+            let candidate_content = self.blob_data(*candidate_oid)?;
+            let score = similarity(
+                content.to_str_lossy().as_ref(),
+                candidate_content.to_str_lossy().as_ref(),
+            );
+            if score >= RENAME_SIMILARITY && best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, candidate));
+            }
+        }
+        Ok(best.map(|(_, candidate)| candidate.to_string()))
+    }
+
+    /// Lists the blobs under the given path, keyed by their path from the root of the repository
+    fn blob_ids(&self, ref_or_sha1: &str, path: &Path) -> Result<Vec<(String, ObjectId)>> {
+        let commit = match try_find_commit(&self.repo, ref_or_sha1)? {
+            Some(commit) => commit,
+            None => bail!("Didn't find source commit for ref {}", ref_or_sha1),
+        };
+        self.commit_blob_ids(&self.repo, &commit, Path::new(""), path)
+    }
+
+    /// Lists the blobs under `path` in a commit of `repo`, recursing into submodules.
+    /// `prefix` is the path of `repo` in the superproject.
+    fn commit_blob_ids(
+        &self,
+        repo: &Repository,
+        commit: &Commit,
+        prefix: &Path,
+        path: &Path,
+    ) -> Result<Vec<(String, ObjectId)>> {
+        let submodules = match commit.tree()?.lookup_entry_by_path(GITMODULES_FILE_NAME)? {
+            Some(entry) => submodules::parse(entry.object()?.data.to_str()?),
+            None => vec![],
+        };
+
+        if let Some(submodule) = submodules
+            .iter()
+            .find(|submodule| path.starts_with(&submodule.path))
+        {
+            return match commit.tree()?.lookup_entry_by_path(&submodule.path)? {
+                Some(entry) if entry.mode() == EntryMode::Commit => self.submodule_blob_ids(
+                    repo,
+                    submodule,
+                    entry.oid().to_owned(),
+                    prefix,
+                    path.strip_prefix(&submodule.path)?,
+                ),
+                _ => Ok(vec![]),
+            };
+        }
+
+        let (tree, tree_path) = if path.as_os_str().is_empty() || path == Path::new(".") {
+            (commit.tree()?, Path::new(""))
+        } else {
+            match commit.tree()?.lookup_entry_by_path(path)? {
+                Some(entry) if entry.mode() == EntryMode::Tree => {
+                    (entry.object()?.into_tree(), path)
+                }
+                Some(entry) if entry.mode() == EntryMode::Blob => {
+                    return Ok(vec![(
+                        prefix.join(path).to_str().unwrap().to_string(),
+                        entry.oid().to_owned(),
+                    )])
+                }
+                _ => return Ok(vec![]),
+            }
+        };
+
+        let mut recorder = Recorder::default();
+        tree.traverse().breadthfirst::<Recorder>(&mut recorder)?;
+
+        let mut blobs = vec![];
+        for entry in recorder.records {
+            let entry_path = tree_path.join(entry.filepath.to_path()?);
+            match entry.mode {
+                EntryMode::Blob => blobs.push((
+                    prefix.join(&entry_path).to_str().unwrap().to_string(),
+                    entry.oid,
+                )),
+                EntryMode::Commit => {
+                    match submodules
+                        .iter()
+                        .find(|submodule| Path::new(&submodule.path) == entry_path)
+                    {
+                        Some(submodule) => blobs.extend(self.submodule_blob_ids(
+                            repo,
+                            submodule,
+                            entry.oid,
+                            prefix,
+                            Path::new(""),
+                        )?),
+                        None => eprintln!(
+                            "warning: {} is not declared in {}, it is skipped",
+                            prefix.join(&entry_path).display(),
+                            GITMODULES_FILE_NAME
+                        ),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(blobs)
+    }
+
+    /// Lists the blobs under `path` in a submodule, at the commit pinned by the superproject
+    fn submodule_blob_ids(
+        &self,
+        repo: &Repository,
+        submodule: &Submodule,
+        oid: ObjectId,
+        prefix: &Path,
+        path: &Path,
+    ) -> Result<Vec<(String, ObjectId)>> {
+        let submodule_repo = open_submodule(repo, submodule, oid)?;
+        let blobs = {
+            let commit = submodule_repo.find_object(oid)?.try_into_commit()?;
+            self.commit_blob_ids(
+                &submodule_repo,
+                &commit,
+                &prefix.join(&submodule.path),
+                path,
+            )?
+        };
+
+        let mut submodules = self.submodules.borrow_mut();
+        if !submodules
+            .iter()
+            .any(|r| r.git_dir() == submodule_repo.git_dir())
+        {
+            submodules.push(submodule_repo);
+        }
+        Ok(blobs)
+    }
 
-            let script = merge_sql_scripts(&scripts)?;
+    /// Reads a blob from the repository, or from one of its submodules
+    fn blob_data(&self, oid: ObjectId) -> Result<Vec<u8>> {
+        if let Ok(object) = self.repo.find_object(oid) {
+            return Ok(object.data.clone());
+        }
+        for submodule in self.submodules.borrow().iter() {
+            if let Ok(object) = submodule.find_object(oid) {
+                return Ok(object.data.clone());
+            }
+        }
+        bail!("Could not find object with id {}", oid)
+    }
+}
+
+/// Opens the repository of a submodule containing the given commit.
+/// Looks for the repository git keeps in the superproject, the checked out submodule, then a local url.
+fn open_submodule(repo: &Repository, submodule: &Submodule, oid: ObjectId) -> Result<Repository> {
+    let mut candidates = vec![
+        repo.git_dir().join("modules").join(&submodule.name),
+        repo.common_dir().join("modules").join(&submodule.name),
+    ];
+    if let Some(work_dir) = repo.work_dir() {
+        candidates.push(work_dir.join(&submodule.path));
+    }
+    if let Some(url) = &submodule.url {
+        if Path::new(url).is_absolute() {
+            candidates.push(PathBuf::from(url));
+        } else if url.starts_with("./") || url.starts_with("../") {
+            // relative urls are relative to the superproject
+            candidates.push(repo.work_dir().unwrap_or(repo.git_dir()).join(url));
+        }
+    }
+
+    for candidate in candidates.iter().filter(|candidate| candidate.exists()) {
+        if let Ok(mut submodule_repo) = git_repository::open(candidate) {
+            if submodule_repo.find_object(oid).is_ok() {
+                submodule_repo.object_cache_size_if_unset(OBJECT_CACHE_SIZE);
+                return Ok(submodule_repo);
+            }
+        }
+    }
+    bail!(
+        "Commit {} of submodule {} is not available, it may need to be fetched with `git submodule update --init`",
+        oid,
+        submodule.path
+    )
+}
+
+/// A schema, or some of its files, renamed between the source and target revisions
+#[derive(Debug, PartialEq, Eq)]
+pub struct SchemaRename {
+    /// Path of the schema at the source revision
+    pub source_path: String,
+    /// Path of the schema at the target revision
+    pub target_path: String,
+    /// Renamed files, as `(source, target)` pairs of paths
+    pub files: Vec<(String, String)>,
+}
+
+impl SchemaRename {
+    /// Maps the source path of each renamed file to its target path
+    pub fn file_map(&self) -> HashMap<String, String> {
+        self.files.iter().cloned().collect()
+    }
+}
 
-            Ok(script)
+impl fmt::Display for SchemaRename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.source_path != self.target_path {
+            writeln!(
+                f,
+                "Detected rename: {} -> {}",
+                self.source_path, self.target_path
+            )?;
         }
+        for (source, target) in &self.files {
+            // files only moved along with the schema are not worth reporting
+            if moved_path(source, &self.source_path, &self.target_path).as_deref() != Some(target) {
+                writeln!(f, "Detected rename: {} -> {}", source, target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn trim_schema_path(schema_path: &str) -> &Path {
+    let schema_path = Path::new(schema_path);
+    schema_path.strip_prefix("./").unwrap_or(schema_path)
+}
+
+/// Normalizes a schema path to the form it has in the repository, e.g. `./schema/` to `schema`
+pub fn normalize_schema_path(schema_path: &str) -> String {
+    Path::new(schema_path)
+        .components()
+        .filter(|component| component != &Component::CurDir)
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns the path of a file or directory relative to the root of the working tree containing it
+pub fn working_tree_path(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    let repo = git_repository::discover(&path).ok()?;
+    let root = repo.work_dir()?.canonicalize().ok()?;
+    Some(normalize_schema_path(
+        path.strip_prefix(root).ok()?.to_str()?,
+    ))
+}
+
+fn file_name(path: &str) -> Option<&OsStr> {
+    Path::new(path).file_name()
+}
+
+/// Returns the path `path` would have if `source_root` was moved to `target_root`
+fn moved_path(path: &str, source_root: &str, target_root: &str) -> Option<String> {
+    if path == source_root {
+        return Some(target_root.to_string());
+    }
+    let relative = Path::new(path).strip_prefix(source_root).ok()?;
+    Some(Path::new(target_root).join(relative).to_str()?.to_string())
+}
+
+/// Infers where the schema used to live from its renamed files. The most common location wins.
+fn source_root(schema_path: &Path, files: &[(String, String)]) -> Option<String> {
+    let mut votes: BTreeMap<String, usize> = BTreeMap::new();
+    for (source, target) in files {
+        let target = Path::new(target);
+        if target == schema_path {
+            return Some(source.clone());
+        }
+        let Ok(relative) = target.strip_prefix(schema_path) else {
+            continue;
+        };
+        let source = Path::new(source);
+        if source.ends_with(relative) {
+            let mut root = source.to_path_buf();
+            for _ in relative.components() {
+                root.pop();
+            }
+            *votes.entry(root.to_str()?.to_string()).or_default() += 1;
+        }
+    }
+
+    // on ties, the first root in lexicographic order wins
+    votes
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(root, _)| root)
+}
+
+/// Lines which are neither blank nor only made of punctuation such as `);`
+fn significant_lines(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.chars().any(char::is_alphanumeric))
+        .collect()
+}
+
+/// Line-based similarity of two texts, between 0 and 1, ignoring lines which are common to unrelated files
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (significant_lines(a), significant_lines(b));
+    let mut lines: HashMap<&str, usize> = HashMap::new();
+    for line in &a {
+        *lines.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in &b {
+        if let Some(count) = lines.get_mut(line).filter(|count| **count > 0) {
+            *count -= 1;
+            common += 1;
+        }
+    }
+
+    let total = a.len() + b.len();
+    if total == 0 {
+        0.0
     } else {
-        bail!("Didn't find source commit for ref {}", ref_or_sha1);
+        (2 * common) as f64 / total as f64
+    }
+}
+
+pub fn get_schema_files(
+    repo_path: &str,
+    ref_or_sha1: &str,
+    schema_path: &str,
+) -> Result<SchemaFiles> {
+    SchemaRepository::open(repo_path)?.schema_files(ref_or_sha1, schema_path)
+}
+
+/// Reads the SQL files of a schema from the file system.
+/// The files are keyed by their path relative to `base_path`.
+pub fn read_schema_files(base_path: &Path, schema_path: &Path) -> Result<SchemaFiles> {
+    let sql_extension = Some(OsStr::new("sql"));
+    let root = base_path.join(schema_path);
+
+    let mut scripts = HashMap::new();
+    for entry in WalkDir::new(&root).into_iter().filter_map(Result::ok) {
+        if entry.path().extension() == sql_extension {
+            let path = entry.path().strip_prefix(base_path)?;
+            scripts.insert(
+                path.to_str().unwrap().to_string(),
+                fs::read_to_string(entry.path())?,
+            );
+        }
     }
+
+    Ok(SchemaFiles {
+        root: schema_path.to_owned(),
+        scripts,
+        manifest: fs::read_to_string(root.join(MANIFEST_FILE_NAME)).ok(),
+    })
 }
 
 fn try_find_commit<'repo>(
     repo: &'repo Repository,
     ref_or_sha1: &str,
 ) -> Result<Option<Commit<'repo>>> {
-    let object_option = repo.rev_parse_single(ref_or_sha1)?.object();
+    if let Some((a, b)) = ref_or_sha1.split_once("...") {
+        let (a, b) = (or_head(a), or_head(b));
+        return match (try_find_commit(repo, a)?, try_find_commit(repo, b)?) {
+            (Some(a_commit), Some(b_commit)) => match merge_base(repo, &a_commit, &b_commit)? {
+                Some(base) => Ok(Some(base)),
+                None => bail!("{} and {} have no common ancestor", a, b),
+            },
+            _ => Ok(None),
+        };
+    }
+    if ref_or_sha1.contains("..") {
+        bail!(
+            "{} is a range, which is only supported as the target revision. Use A...B to select the merge base of A and B",
+            ref_or_sha1
+        );
+    }
+
+    let object = match repo.rev_parse_single(ref_or_sha1)?.object() {
+        Ok(object) => object,
+        Err(_) => return Ok(None),
+    };
+    let kind = object.kind;
+    let peeled = object.peel_tags_to_end()?;
+
+    match peeled.kind {
+        Kind::Commit => Ok(Some(peeled.into_commit())),
+        actual if kind == Kind::Tag => {
+            bail!(
+                "{} is a tag pointing to a {}, not a commit",
+                ref_or_sha1,
+                actual
+            )
+        }
+        actual => bail!(
+            "{} resolves to a {}, not a commit. Use a branch, a tag or a commit id",
+            ref_or_sha1,
+            actual
+        ),
+    }
+}
+
+/// Returns the best common ancestor of two commits, i.e. a common ancestor which is not an ancestor of another common ancestor
+fn merge_base<'repo>(
+    repo: &'repo Repository,
+    a: &Commit<'repo>,
+    b: &Commit<'repo>,
+) -> Result<Option<Commit<'repo>>> {
+    let a_ancestors = a
+        .ancestors()
+        .all()?
+        .map(|id| id.map(|id| id.detach()))
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    let mut common = vec![];
+    for id in b.ancestors().all()? {
+        let id = id?.detach();
+        if a_ancestors.contains(&id) {
+            common.push(id);
+        }
+    }
+
+    // the ancestors of a common ancestor are all common ancestors, hence parents of a common ancestor
+    let mut parents = HashSet::new();
+    for id in &common {
+        let commit = repo.find_object(*id)?.try_into_commit()?;
+        parents.extend(commit.parent_ids().map(|id| id.detach()));
+    }
+
+    match common.into_iter().find(|id| !parents.contains(id)) {
+        Some(id) => Ok(Some(repo.find_object(id)?.try_into_commit()?)),
+        None => Ok(None),
+    }
+}
+
+/// An empty side of a range stands for `HEAD`, as in git
+fn or_head(rev: &str) -> &str {
+    if rev.is_empty() {
+        "HEAD"
+    } else {
+        rev
+    }
+}
 
-    if let Ok(object) = object_option {
-        let commit = object.try_into_commit()?;
-        Ok(Some(commit))
+/// Splits a `A..B` or `A...B` target revision into the source and target revisions, like `git diff` does.
+/// With `A...B`, the source is the merge base of A and B.
+pub fn split_range(to: &str) -> Option<(String, String)> {
+    if let Some((a, b)) = to.split_once("...") {
+        Some((
+            format!("{}...{}", or_head(a), or_head(b)),
+            or_head(b).to_string(),
+        ))
     } else {
-        Ok(None)
+        to.split_once("..")
+            .map(|(a, b)| (or_head(a).to_string(), or_head(b).to_string()))
     }
 }
 
@@ -89,42 +774,117 @@ fn normalize_path(path: &Path) -> PathBuf {
     normalized
 }
 
-pub fn merge_sql_scripts(sql_scripts: &HashMap<&str, &str>) -> Result<String> {
+/// Returns the `-- import` and psql `\\i` dependencies between scripts, as `(dependency, dependent)` pairs of paths
+pub fn import_edges(sql_scripts: &HashMap<&str, &str>) -> Vec<(String, String)> {
     let import_regex = Regex::new(r"(?m)^.*--\s*import\s+(.*)$").unwrap();
-    let mut graph = DiGraphMap::<&str, ()>::new();
+
+    let mut ordered_keys: Vec<&&str> = sql_scripts.keys().collect();
+    ordered_keys.sort();
 
     let mut edges: Vec<(String, String)> = vec![];
-    for (k, v) in sql_scripts.iter() {
-        graph.add_node(k);
+    for (k, v) in ordered_keys.iter().map(|k| (k, sql_scripts[**k])) {
+        let imports = import_regex
+            .captures_iter(v)
+            .map(|group| (group[1].to_string(), false))
+            .chain(
+                psql::find_includes(v)
+                    .into_iter()
+                    .map(|include| (include.path, include.relative)),
+            );
+
+        // files imported from another repository resolve their imports within that repository
+        let (prefix, k_path) = external::split_key(k);
 
-        for group in import_regex.captures_iter(v) {
-            let mut import_path = PathBuf::from(group[1].to_string());
+        for (path, relative) in imports {
+            if path.starts_with('@') {
+                edges.push((path, k.to_string()));
+                continue;
+            }
+            let mut import_path = PathBuf::from(&path);
             let first_component = import_path.components().next();
-            if first_component == Some(std::path::Component::CurDir)
+            if relative
+                || first_component == Some(std::path::Component::CurDir)
                 || first_component == Some(std::path::Component::ParentDir)
             {
-                import_path = PathBuf::from(k);
+                import_path = PathBuf::from(k_path);
                 import_path.pop();
-                import_path.push(PathBuf::from(group[1].to_string()));
+                import_path.push(PathBuf::from(&path));
             }
             let normalized_path = normalize_path(import_path.as_path());
-            edges.push((normalized_path.display().to_string(), k.to_string()));
+            edges.push((
+                format!("{}{}", prefix, normalized_path.display()),
+                k.to_string(),
+            ));
         }
     }
+    edges
+}
 
-    let str_edges: Vec<(&str, &str)> = edges
-        .iter()
-        .map(|(x, y)| (x.as_str(), y.as_str()))
-        .collect();
+/// The reason why a file is loaded before another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// `-- import` comment or psql `\i` meta-command
+    Import,
+    /// Reference to an object defined in the other file
+    Inferred,
+    /// Order of the files listed in the manifest
+    Manifest,
+    /// Lexicographic order of the paths, for files without any other dependency
+    Lexicographic,
+}
+
+/// Builds the graph of the dependencies between scripts. Edges go from a dependency to its dependent.
+pub fn dependency_graph<'a>(
+    sql_scripts: &HashMap<&'a str, &str>,
+    options: &MergeOptions,
+) -> Result<DiGraphMap<&'a str, EdgeKind>> {
+    let mut graph = DiGraphMap::<&str, EdgeKind>::new();
 
-    for e in str_edges {
-        graph.add_edge(e.0, e.1, ());
+    let mut ordered_keys: Vec<&str> = sql_scripts.keys().copied().collect();
+    ordered_keys.sort();
+    for k in &ordered_keys {
+        graph.add_node(k);
     }
 
-    let mut ordered_keys: Vec<&&str> = sql_scripts.keys().collect();
-    if ordered_keys.len() > 1 {
-        ordered_keys.sort();
+    for (dependency, dependent) in import_edges(sql_scripts) {
+        let renamed = options.renames.get(&dependency);
+        match sql_scripts
+            .get_key_value(dependency.as_str())
+            .or_else(|| sql_scripts.get_key_value(renamed?.as_str()))
+        {
+            Some((dependency, _)) => {
+                let dependent = sql_scripts.get_key_value(dependent.as_str()).unwrap().0;
+                graph.add_edge(dependency, dependent, EdgeKind::Import);
+            }
+            None => eprintln!(
+                "warning: {} imports {}, which is not part of the schema",
+                dependent, dependency
+            ),
+        }
+    }
+
+    if options.infer_dependencies {
+        for (dependency, dependent) in dependencies::infer_edges(sql_scripts) {
+            let dependency = sql_scripts.get_key_value(dependency.as_str()).unwrap().0;
+            let dependent = sql_scripts.get_key_value(dependent.as_str()).unwrap().0;
+            // explicit imports take precedence over inferred dependencies
+            if !graph.contains_edge(dependency, dependent)
+                && !has_path_connecting(&graph, dependent, dependency, None)
+            {
+                graph.add_edge(dependency, dependent, EdgeKind::Inferred);
+            }
+        }
+    }
 
+    if let Some(manifest) = &options.manifest {
+        let resolved = manifest.resolve(&ordered_keys, options.target.as_deref())?;
+        for (dependent, dependency) in apply_manifest(&mut graph, &resolved)? {
+            eprintln!(
+                "warning: {} seems to depend on {}, which the manifest loads after it",
+                dependent, dependency
+            );
+        }
+    } else if ordered_keys.len() > 1 {
         // make sure every node has an edge, to have a deterministic topo sort
         for i in 0..ordered_keys.len() {
             if graph
@@ -137,24 +897,133 @@ pub fn merge_sql_scripts(sql_scripts: &HashMap<&str, &str>) -> Result<String> {
                     == 0
             {
                 if i == 0 {
-                    graph.add_edge(ordered_keys[0], ordered_keys[1], ());
+                    graph.add_edge(ordered_keys[0], ordered_keys[1], EdgeKind::Lexicographic);
                 } else {
-                    graph.add_edge(ordered_keys[i - 1], ordered_keys[i], ());
+                    graph.add_edge(
+                        ordered_keys[i - 1],
+                        ordered_keys[i],
+                        EdgeKind::Lexicographic,
+                    );
                 }
             }
         }
     }
 
-    let sorted_nodes = toposort(&graph, None);
-    match sorted_nodes {
-        Ok(nodes) => Ok(nodes
-            .iter()
-            .filter_map(|key| sql_scripts.get(key))
-            .copied()
-            .collect::<Vec<&str>>()
-            .join("\n")),
-        Err(_) => bail!("Dependency cycle found."),
+    Ok(graph)
+}
+
+/// Returns the scripts in the order they are loaded: dependencies first, and in lexicographic order otherwise
+pub fn load_order<'a>(graph: &DiGraphMap<&'a str, EdgeKind>) -> Result<Vec<&'a str>> {
+    let mut incoming: HashMap<&str, usize> = graph
+        .nodes()
+        .map(|node| {
+            let count = graph.neighbors_directed(node, Direction::Incoming).count();
+            (node, count)
+        })
+        .collect();
+    let mut ready: BinaryHeap<Reverse<&str>> = incoming
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(node, _)| Reverse(*node))
+        .collect();
+
+    let mut order = Vec::with_capacity(graph.node_count());
+    while let Some(Reverse(node)) = ready.pop() {
+        order.push(node);
+        for dependent in graph.neighbors_directed(node, Direction::Outgoing) {
+            let count = incoming.get_mut(dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(Reverse(dependent));
+            }
+        }
     }
+
+    if order.len() < graph.node_count() {
+        bail!("Dependency cycle found.");
+    }
+    Ok(order)
+}
+
+pub fn merge_sql_scripts(
+    sql_scripts: &HashMap<&str, &str>,
+    options: &MergeOptions,
+) -> Result<SqlScript> {
+    let graph = dependency_graph(sql_scripts, options)?;
+
+    // psql variables persist across files, in load order
+    let mut vars = options.vars.clone();
+    let mut script = SqlScript::default();
+    for path in load_order(&graph)? {
+        let text = template::expand(path, sql_scripts[path], &options.vars)?;
+        script.push(path, &psql::preprocess(path, &text, &mut vars)?);
+    }
+    Ok(script)
+}
+
+/// Orders the files listed in the manifest, and removes the files which are neither listed nor imported by a listed file.
+/// Returns the inferred dependencies the manifest overrides, as (dependent, dependency) pairs.
+fn apply_manifest<'a>(
+    graph: &mut DiGraphMap<&'a str, EdgeKind>,
+    resolved: &ResolvedManifest<'a>,
+) -> Result<Vec<(&'a str, &'a str)>> {
+    for (i, file) in resolved.listed.iter().enumerate() {
+        for dependency in graph.neighbors_directed(file, Direction::Incoming) {
+            // inferred dependencies contradicting the manifest are dropped below
+            if resolved.listed[i + 1..].contains(&dependency)
+                && graph.edge_weight(dependency, file) != Some(&EdgeKind::Inferred)
+            {
+                bail!(
+                    "{} depends on {}, which is listed after it in the manifest",
+                    file,
+                    dependency
+                );
+            }
+        }
+    }
+
+    let mut covered = HashSet::new();
+    let mut stack = resolved.listed.clone();
+    while let Some(file) = stack.pop() {
+        if covered.insert(file) {
+            stack.extend(graph.neighbors_directed(file, Direction::Incoming));
+        }
+    }
+
+    let uncovered: Vec<&str> = graph.nodes().filter(|n| !covered.contains(n)).collect();
+    for file in uncovered {
+        if !resolved.other_targets.contains(file) {
+            eprintln!(
+                "warning: {} is neither listed in the manifest nor imported, it will not be loaded",
+                file
+            );
+        }
+        graph.remove_node(file);
+    }
+
+    for files in resolved.listed.windows(2) {
+        if !graph.contains_edge(files[0], files[1]) {
+            graph.add_edge(files[0], files[1], EdgeKind::Manifest);
+        }
+    }
+
+    // the manifest takes precedence over inferred dependencies, which may be false positives
+    let inferred: Vec<(&str, &str)> = graph
+        .all_edges()
+        .filter(|(_, _, kind)| **kind == EdgeKind::Inferred)
+        .map(|(dependency, dependent, _)| (dependency, dependent))
+        .collect();
+    let mut overridden = vec![];
+    for (dependency, dependent) in inferred {
+        graph.remove_edge(dependency, dependent);
+        if has_path_connecting(&*graph, dependent, dependency, None) {
+            overridden.push((dependent, dependency));
+        } else {
+            graph.add_edge(dependency, dependent, EdgeKind::Inferred);
+        }
+    }
+
+    Ok(overridden)
 }
 
 #[test]
@@ -171,7 +1040,7 @@ create table foo.bar(
     );
     scripts.insert("schema/b", r#"create schema foo;"#);
 
-    let merged_script = merge_sql_scripts(&scripts);
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default());
     assert_eq!(
         r#"create schema foo;
 -- import schema/b
@@ -181,7 +1050,7 @@ create table foo.bar(
 );
 "#
         .to_string(),
-        merged_script.unwrap()
+        merged_script.unwrap().text
     );
 }
 
@@ -194,8 +1063,8 @@ fn it_merges_sql_scripts_in_bfs_order() {
     scripts.insert("a/f/g", "4");
     scripts.insert("a/f/h", "5");
 
-    let merged_script = merge_sql_scripts(&scripts);
-    assert_eq!("1\n2\n3\n4\n5".to_string(), merged_script.unwrap());
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default());
+    assert_eq!("1\n2\n3\n4\n5".to_string(), merged_script.unwrap().text);
 }
 
 #[test]
@@ -212,8 +1081,8 @@ e"#,
     scripts.insert("a/f/g", "g");
     scripts.insert("a/f/h", "h");
 
-    let merged_script = merge_sql_scripts(&scripts).unwrap();
-    let lines: Vec<&str> = merged_script.lines().collect();
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default()).unwrap();
+    let lines: Vec<&str> = merged_script.text.lines().collect();
 
     assert!(
         lines.iter().position(|l| l.starts_with('d')).unwrap()
@@ -239,8 +1108,8 @@ e"#,
     );
     scripts.insert("b/d/f", "f");
 
-    let merged_script = merge_sql_scripts(&scripts).unwrap();
-    let lines: Vec<&str> = merged_script.lines().collect();
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default()).unwrap();
+    let lines: Vec<&str> = merged_script.text.lines().collect();
 
     assert!(
         lines.iter().position(|l| l.starts_with('c')).unwrap()
@@ -251,3 +1120,236 @@ e"#,
             < lines.iter().position(|l| l.starts_with('e')).unwrap()
     );
 }
+
+#[test]
+fn it_orders_psql_includes() {
+    let mut scripts = HashMap::new();
+    scripts.insert(
+        "a/1_table.sql",
+        "\\ir ../b/types.sql\ncreate table t (s my_type);",
+    );
+    scripts.insert(
+        "b/types.sql",
+        "\\i a/0_schema.sql\ncreate type my_type as enum ('a');",
+    );
+    scripts.insert("a/0_schema.sql", "create schema s;");
+
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default()).unwrap();
+
+    assert_eq!(
+        "create schema s;\n\ncreate type my_type as enum ('a');\n\ncreate table t (s my_type);",
+        merged_script.text
+    );
+}
+
+#[test]
+fn it_expands_variables() {
+    let mut scripts = HashMap::new();
+    scripts.insert("a", "create role {{ role }};");
+    scripts.insert("b", "grant usage on schema app to :role;");
+    let options = MergeOptions {
+        vars: HashMap::from([("role".to_string(), "app_user".to_string())]),
+        ..MergeOptions::default()
+    };
+
+    let merged_script = merge_sql_scripts(&scripts, &options).unwrap();
+
+    assert_eq!(
+        "create role app_user;\ngrant usage on schema app to app_user;",
+        merged_script.text
+    );
+}
+
+#[test]
+fn it_merges_scripts_in_manifest_order() {
+    let mut scripts = HashMap::new();
+    scripts.insert("db/a_table.sql", "-- import db/types/b.sql\ntable");
+    scripts.insert("db/types/b.sql", "type");
+    scripts.insert("db/z_schema.sql", "schema");
+    scripts.insert("db/scratch.sql", "scratch");
+    scripts.insert("db/grants/prod.sql", "prod grants");
+    let manifest = Manifest::parse(
+        r#"
+        files = ["z_schema.sql", "a_table.sql"]
+
+        [targets.prod]
+        files = ["grants/*.sql"]
+        "#,
+    )
+    .unwrap();
+    let options = MergeOptions {
+        manifest: Some(manifest.with_root(Path::new("db"))),
+        ..MergeOptions::default()
+    };
+
+    let merged_script = merge_sql_scripts(&scripts, &options).unwrap();
+
+    assert_eq!(
+        "type\nschema\n-- import db/types/b.sql\ntable",
+        merged_script.text
+    );
+}
+
+#[test]
+fn it_gives_precedence_to_the_manifest_over_inferred_dependencies() {
+    let mut scripts = HashMap::new();
+    scripts.insert("db/views.sql", "create view app.v as select app.f();");
+    scripts.insert(
+        "db/functions.sql",
+        "create function app.f() returns int as $$ select 1 $$ language sql;",
+    );
+    let manifest = Manifest::parse(r#"files = ["views.sql", "functions.sql"]"#)
+        .unwrap()
+        .with_root(Path::new("db"));
+    let mut graph = dependency_graph(&scripts, &MergeOptions::default()).unwrap();
+    assert_eq!(
+        Some(&EdgeKind::Inferred),
+        graph.edge_weight("db/functions.sql", "db/views.sql")
+    );
+
+    let paths: Vec<&str> = scripts.keys().copied().collect();
+    let resolved = manifest.resolve(&paths, None).unwrap();
+    let overridden = apply_manifest(&mut graph, &resolved).unwrap();
+
+    assert_eq!(vec![("db/views.sql", "db/functions.sql")], overridden);
+    assert_eq!(
+        vec!["db/views.sql", "db/functions.sql"],
+        load_order(&graph).unwrap()
+    );
+}
+
+#[test]
+fn it_infers_dependencies() {
+    let mut scripts = HashMap::new();
+    scripts.insert(
+        "a_todo.sql",
+        "create table app.todo (user_id int references app.user);",
+    );
+    scripts.insert("b_user.sql", "create table app.user (id int primary key);");
+    scripts.insert("c_schema.sql", "create schema app;");
+
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default()).unwrap();
+
+    assert_eq!(
+        "create schema app;\ncreate table app.user (id int primary key);\ncreate table app.todo (user_id int references app.user);",
+        merged_script.text
+    );
+}
+
+#[test]
+fn it_keeps_the_lexicographic_order_of_files_with_inferred_dependencies() {
+    let mut scripts = HashMap::new();
+    scripts.insert("00_schema.sql", "create schema app;");
+    scripts.insert("01_roles.sql", "create role app_user;");
+    scripts.insert(
+        "02_tables.sql",
+        "create table app.t (id int);\ngrant select on app.t to app_user;",
+    );
+    scripts.insert("03_more.sql", "create table app.u (id int);");
+
+    let graph = dependency_graph(&scripts, &MergeOptions::default()).unwrap();
+
+    assert_eq!(
+        vec![
+            "00_schema.sql",
+            "01_roles.sql",
+            "02_tables.sql",
+            "03_more.sql"
+        ],
+        load_order(&graph).unwrap()
+    );
+}
+
+#[test]
+fn it_gives_precedence_to_imports_over_inferred_dependencies() {
+    let mut scripts = HashMap::new();
+    scripts.insert(
+        "a.sql",
+        "create function app.f() returns int as $$ select 1 $$ language sql;",
+    );
+    scripts.insert("b.sql", "-- import c.sql\nselect app.f();");
+    scripts.insert("c.sql", "-- import a.sql\ncreate schema app;");
+
+    let merged_script = merge_sql_scripts(&scripts, &MergeOptions::default()).unwrap();
+    let lines: Vec<&str> = merged_script.text.lines().collect();
+
+    assert!(
+        lines
+            .iter()
+            .position(|l| l.starts_with("create function"))
+            .unwrap()
+            < lines
+                .iter()
+                .position(|l| l.starts_with("create schema"))
+                .unwrap()
+    );
+}
+
+#[test]
+fn it_splits_ranges() {
+    assert_eq!(
+        Some(("main".to_string(), "feature".to_string())),
+        split_range("main..feature")
+    );
+    assert_eq!(
+        Some(("main...HEAD".to_string(), "HEAD".to_string())),
+        split_range("main...")
+    );
+    assert_eq!(None, split_range("HEAD~1"));
+}
+
+#[test]
+fn it_infers_the_source_root_of_renamed_files() {
+    let files = [
+        ("db/schema/a.sql", "schema/a.sql"),
+        ("db/schema/types/b.sql", "schema/types/b.sql"),
+        ("other/c.sql", "schema/c.sql"),
+        ("db/schema/d.sql", "schema/e.sql"),
+    ]
+    .map(|(source, target)| (source.to_string(), target.to_string()));
+
+    assert_eq!(
+        Some("db/schema".to_string()),
+        source_root(Path::new("schema"), &files)
+    );
+    assert_eq!(
+        Some("db/schema/d.sql".to_string()),
+        source_root(Path::new("schema/e.sql"), &files)
+    );
+}
+
+#[test]
+fn it_measures_similarity() {
+    assert_eq!(1.0, similarity("a\nb\n", "a\nb\n"));
+    assert_eq!(0.5, similarity("a\nb\n", "a\nc\n"));
+    assert_eq!(0.0, similarity("a\n", "b\n"));
+    assert_eq!(0.0, similarity("\n);\n", "\n);\n"));
+}
+
+#[test]
+fn it_resolves_imports_of_renamed_files() {
+    let mut scripts = HashMap::new();
+    scripts.insert("a.sql", "-- import old.sql\nselect 1;");
+    scripts.insert("new.sql", "select 2;");
+    let options = MergeOptions {
+        renames: HashMap::from([("old.sql".to_string(), "new.sql".to_string())]),
+        infer_dependencies: false,
+        ..MergeOptions::default()
+    };
+
+    let merged_script = merge_sql_scripts(&scripts, &options).unwrap();
+
+    assert_eq!(
+        "select 2;\n-- import old.sql\nselect 1;",
+        merged_script.text
+    );
+}
+
+#[test]
+fn it_normalizes_schema_paths() {
+    for path in ["schema", "schema/", "./schema", "./schema/"] {
+        assert_eq!("schema", normalize_schema_path(path));
+    }
+    assert_eq!("db/schema.sql", normalize_schema_path("./db//schema.sql"));
+    assert_eq!("", normalize_schema_path("."));
+}
@@ -0,0 +1,209 @@
+use anyhow::anyhow;
+use tokio_postgres::error::ErrorPosition;
+
+use crate::db::StatementError;
+
+/// A SQL script, possibly merged from multiple files, along with the information needed to trace its content back to the original files
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SqlScript {
+    pub text: String,
+    pub source_map: SourceMap,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    /// Byte offset of the segment in the script
+    start: usize,
+    path: String,
+}
+
+/// A location in one of the files a script was built from. Lines and columns are 1-based
+#[derive(Debug, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    pub path: &'a str,
+    pub line: usize,
+    pub column: usize,
+    /// The content of the line, as sent to the server
+    pub line_text: &'a str,
+}
+
+impl SqlScript {
+    /// Creates a script made of a single source, e.g. a file or a generated migration
+    pub fn new(path: &str, text: String) -> SqlScript {
+        let mut script = SqlScript::default();
+        script.push(path, &text);
+        script
+    }
+
+    /// Appends the content of a file to the script, separated from the previous file by a newline
+    pub fn push(&mut self, path: &str, text: &str) {
+        if !self.source_map.segments.is_empty() {
+            self.text.push('\n');
+        }
+        self.source_map.segments.push(Segment {
+            start: self.text.len(),
+            path: path.to_string(),
+        });
+        self.text.push_str(text);
+    }
+
+    /// Returns the path and content of the files the script was built from
+    pub fn files(&self) -> Vec<(&str, &str)> {
+        let segments = &self.source_map.segments;
+        segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                // files are separated by a newline
+                let end = segments
+                    .get(i + 1)
+                    .map_or(self.text.len(), |next| next.start - 1);
+                (segment.path.as_str(), &self.text[segment.start..end])
+            })
+            .collect()
+    }
+
+    /// Finds the original location of a byte offset in the script
+    pub fn locate(&self, offset: usize) -> Option<SourceLocation<'_>> {
+        let segment = self
+            .source_map
+            .segments
+            .iter()
+            .rev()
+            .find(|s| s.start <= offset)?;
+        let offset = offset.min(self.text.len());
+
+        let before = &self.text[segment.start..offset];
+        let line_start = before
+            .rfind('\n')
+            .map_or(segment.start, |i| segment.start + i + 1);
+        let line_end = self.text[offset..]
+            .find('\n')
+            .map_or(self.text.len(), |i| offset + i);
+
+        Some(SourceLocation {
+            path: &segment.path,
+            line: before.matches('\n').count() + 1,
+            column: self.text[line_start..offset].chars().count() + 1,
+            line_text: &self.text[line_start..line_end],
+        })
+    }
+
+    /// Adds the location of the failing statement to a database error raised while running this script
+    pub fn describe_error(&self, err: anyhow::Error) -> anyhow::Error {
+        // statements run one by one report positions relative to the statement
+        let (db_error, statement_offset, note) = match err.downcast_ref::<StatementError>() {
+            Some(e) => (e.error.as_db_error(), e.offset, e.notes()),
+            None => (
+                err.downcast_ref::<tokio_postgres::Error>()
+                    .and_then(|e| e.as_db_error()),
+                0,
+                None,
+            ),
+        };
+        let db_error = match db_error {
+            Some(db_error) => db_error,
+            None => return err,
+        };
+
+        // the error position is a 1-based index, in characters
+        let position = match db_error.position() {
+            Some(ErrorPosition::Original(position)) => *position as usize,
+            _ => return err,
+        };
+        let statement = self.text.get(statement_offset..).unwrap_or_default();
+        let offset = statement_offset
+            + statement
+                .char_indices()
+                .nth(position.saturating_sub(1))
+                .map_or(statement.len(), |(i, _)| i);
+
+        match self.locate(offset) {
+            Some(location) => anyhow!(
+                "{}: {}\n{}{}",
+                db_error.severity(),
+                db_error.message(),
+                location,
+                note.map_or(String::new(), |note| format!("\n{}", note))
+            ),
+            None => err,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let caret_padding: String = self
+            .line_text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.path, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line_number, self.line_text)?;
+        write!(f, "{} | {}^", gutter, caret_padding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_locates_offsets_in_merged_scripts() {
+        let mut script = SqlScript::default();
+        script.push("schema/schema.sql", "create schema app;");
+        script.push(
+            "schema/tables/user.sql",
+            "create table app.user (\n    id int,\n    email text,\n);",
+        );
+
+        let offset = script.text.find(");").unwrap();
+        let location = script.locate(offset).unwrap();
+
+        assert_eq!(
+            SourceLocation {
+                path: "schema/tables/user.sql",
+                line: 4,
+                column: 1,
+                line_text: ");"
+            },
+            location
+        );
+        assert_eq!(
+            vec![
+                ("schema/schema.sql", "create schema app;"),
+                (
+                    "schema/tables/user.sql",
+                    "create table app.user (\n    id int,\n    email text,\n);"
+                )
+            ],
+            script.files()
+        );
+    }
+
+    #[test]
+    fn it_displays_locations_with_a_caret() {
+        let script = SqlScript::new("a.sql", "select 1;\nselect fo bar;".to_string());
+
+        let location = script.locate(script.text.find("bar").unwrap()).unwrap();
+
+        assert_eq!(
+            " --> a.sql:2:11\n  |\n2 | select fo bar;\n  |           ^",
+            location.to_string()
+        );
+    }
+}
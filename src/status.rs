@@ -0,0 +1,90 @@
+use serde::Serialize;
+
+use crate::cli::StatusFormat;
+use crate::lexer::split_statements;
+
+/// Result of the comparison of the target database with a schema
+pub struct Status {
+    /// Revision the schema was read from, or `working tree`
+    pub schema: String,
+    pub path: String,
+    /// Statements which would make the target database match the schema
+    pub statements: String,
+}
+
+#[derive(Serialize)]
+struct JsonStatus<'a> {
+    schema: &'a str,
+    path: &'a str,
+    drift: bool,
+    statements: Vec<&'a str>,
+}
+
+impl Status {
+    pub fn has_drift(&self) -> bool {
+        !split_statements(&self.statements).is_empty()
+    }
+
+    pub fn render(&self, format: StatusFormat) -> String {
+        match format {
+            StatusFormat::Text if self.has_drift() => format!(
+                "The target database differs from {} at {}. These statements would reconcile it:\n\n{}",
+                self.path,
+                self.schema,
+                self.statements.trim_end()
+            ),
+            StatusFormat::Text => format!(
+                "The target database matches {} at {}",
+                self.path, self.schema
+            ),
+            StatusFormat::Json => {
+                let json = JsonStatus {
+                    schema: &self.schema,
+                    path: &self.path,
+                    drift: self.has_drift(),
+                    statements: split_statements(&self.statements)
+                        .into_iter()
+                        .map(str::trim)
+                        .collect(),
+                };
+                serde_json::to_string_pretty(&json).unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_the_status() {
+        let status = Status {
+            schema: "main".to_string(),
+            path: "schema".to_string(),
+            statements: "\n-- comment\n".to_string(),
+        };
+        assert!(!status.has_drift());
+        assert_eq!(
+            "The target database matches schema at main",
+            status.render(StatusFormat::Text)
+        );
+
+        let status = Status {
+            statements: "drop table hotfix;\n\ncreate index on t (c);\n".to_string(),
+            ..status
+        };
+        assert!(status.has_drift());
+        let json: serde_json::Value =
+            serde_json::from_str(&status.render(StatusFormat::Json)).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "schema": "main",
+                "path": "schema",
+                "drift": true,
+                "statements": ["drop table hotfix;", "create index on t (c);"]
+            }),
+            json
+        );
+    }
+}
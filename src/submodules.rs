@@ -0,0 +1,88 @@
+use regex::Regex;
+
+pub const GITMODULES_FILE_NAME: &str = ".gitmodules";
+
+/// A submodule declared in `.gitmodules`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Submodule {
+    pub name: String,
+    /// Path of the submodule, relative to the root of the superproject
+    pub path: String,
+    pub url: Option<String>,
+}
+
+/// Parses the submodules declared in a `.gitmodules` file, which uses the git config syntax
+pub fn parse(gitmodules: &str) -> Vec<Submodule> {
+    let section_regex = Regex::new(r#"^\[\s*submodule\s+"(.*)"\s*\]$"#).unwrap();
+
+    let mut submodules: Vec<(String, Option<String>, Option<String>)> = vec![];
+    let mut in_submodule = false;
+    for line in gitmodules.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_submodule = match section_regex.captures(line) {
+                Some(group) => {
+                    submodules.push((group[1].to_string(), None, None));
+                    true
+                }
+                None => false,
+            };
+            continue;
+        }
+        if !in_submodule {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim().trim_matches('"').to_string();
+            let submodule = submodules.last_mut().unwrap();
+            match key.trim().to_lowercase().as_str() {
+                "path" => submodule.1 = Some(value),
+                "url" => submodule.2 = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    submodules
+        .into_iter()
+        .filter_map(|(name, path, url)| {
+            Some(Submodule {
+                name,
+                path: path?.trim_end_matches('/').to_string(),
+                url,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_gitmodules() {
+        let submodules = parse(
+            r#"
+# shared schemas
+[submodule "shared"]
+	path = vendor/shared/
+	url = ../shared.git
+[core]
+	path = ignored
+[submodule "no-path"]
+	url = https://example.com/no-path.git
+"#,
+        );
+
+        assert_eq!(
+            vec![Submodule {
+                name: "shared".to_string(),
+                path: "vendor/shared".to_string(),
+                url: Some("../shared.git".to_string()),
+            }],
+            submodules
+        );
+    }
+}
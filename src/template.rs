@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+/// Replaces the `{{ name }}` placeholders in `text` with the value of the corresponding variable.
+/// `source` is used to locate undefined variables in error messages.
+pub fn expand(source: &str, text: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let placeholder_regex = Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap();
+
+    if let Some(undefined) = placeholder_regex
+        .captures_iter(text)
+        .find(|group| !vars.contains_key(&group[1]))
+    {
+        let start = undefined.get(0).unwrap().start();
+        let line = text[..start].matches('\n').count() + 1;
+        bail!("{}:{}: undefined variable {}", source, line, &undefined[1]);
+    }
+
+    Ok(placeholder_regex
+        .replace_all(text, |group: &Captures| vars[&group[1]].clone())
+        .into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_expands_placeholders() {
+        let vars = HashMap::from([
+            ("role".to_string(), "app_user".to_string()),
+            ("tablespace".to_string(), "fast_ssd".to_string()),
+        ]);
+
+        let output = expand(
+            "a.sql",
+            "create role {{role}};\ncreate table t() tablespace {{ tablespace }};",
+            &vars,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "create role app_user;\ncreate table t() tablespace fast_ssd;",
+            output
+        );
+    }
+
+    #[test]
+    fn it_rejects_undefined_variables() {
+        let err = expand(
+            "schema/a.sql",
+            "select 1;\nselect {{ missing }};",
+            &HashMap::new(),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            "schema/a.sql:2: undefined variable missing",
+            err.to_string()
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use postgit::{self, DiffArgs};
+use std::{fs, path::Path};
 
 mod common;
 pub use common::*;
@@ -121,3 +122,37 @@ fn it_handles_custom_commands() {
         diff_string
     );
 }
+
+#[test]
+fn it_reports_errors_with_file_locations() {
+    let repo = setup();
+    let config = get_config();
+    fs::write(
+        Path::new(&repo.repo_path).join("schema/todo.sql"),
+        r#"-- import schema/user.sql
+create table my_app.todo (
+  id int primary key generated always as identity,
+  task text,
+);"#,
+    )
+    .unwrap();
+    commit_all(&repo.repo_path);
+
+    let args = DiffArgs {
+        from: None,
+        to: "HEAD".to_string(),
+        path: String::from("schema/"),
+        repo_path: repo.repo_path,
+        source_path: None,
+    };
+
+    let err = postgit::get_diff_string(&args, &config).unwrap_err();
+    assert_eq!(
+        r#"ERROR: syntax error at or near ")"
+ --> schema/todo.sql:5:1
+  |
+5 | );
+  | ^"#,
+        err.to_string()
+    );
+}
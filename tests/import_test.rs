@@ -0,0 +1,52 @@
+use postgit::config::RepositoryConfig;
+use postgit::{GraphArgs, GraphFormat, SchemaArgs};
+use std::fs;
+
+mod common;
+pub use common::*;
+
+#[test]
+fn it_imports_files_from_other_repositories() {
+    let platform = setup();
+    let service = setup();
+    let mut config = get_config();
+    config.repositories.insert(
+        "platform".to_string(),
+        RepositoryConfig {
+            path: platform.repo_path.clone(),
+        },
+    );
+    let pinned = format!("@platform:{}:", platform.commits[2]);
+    fs::write(
+        format!("{}/schema/audit.sql", service.repo_path),
+        format!(
+            "-- import {}schema/user.sql\ncreate table my_app.audit (user_id int references my_app.user);",
+            pinned
+        ),
+    )
+    .unwrap();
+
+    let graph = postgit::graph(
+        &GraphArgs {
+            schema: SchemaArgs {
+                repo_path: service.repo_path.clone(),
+                git_ref: None,
+                path: "schema/audit.sql".to_string(),
+            },
+            format: GraphFormat::Json,
+            order: true,
+        },
+        &config,
+    )
+    .unwrap();
+    let graph: serde_json::Value = serde_json::from_str(&graph).unwrap();
+
+    assert_eq!(
+        serde_json::json!([
+            format!("{}schema/schema.sql", pinned),
+            format!("{}schema/user.sql", pinned),
+            "schema/audit.sql"
+        ]),
+        graph["order"]
+    );
+}
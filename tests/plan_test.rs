@@ -0,0 +1,96 @@
+mod common;
+pub use common::*;
+use postgit::{ApplyArgs, LockOptions};
+use tempfile::tempdir;
+
+#[test]
+fn it_applies_plans_if_the_target_did_not_change() {
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+
+    let fingerprint = postgit::db::schema_fingerprint(&target_config).unwrap();
+    execute_statement(&target_config, "create table existing (id int);");
+    let changed_fingerprint = postgit::db::schema_fingerprint(&target_config).unwrap();
+    assert_ne!(fingerprint, changed_fingerprint);
+
+    let dir = tempdir().unwrap();
+    let plan_path = dir.path().join("plan.json");
+    let write_plan = |fingerprint: &str, script: &str| {
+        let plan = serde_json::json!({
+            "version": 1,
+            "from_ref": null,
+            "to_ref": "main",
+            "from_commit": null,
+            "to_commit": "0123456789abcdef",
+            "schema_path": "schema",
+            "schema_hash": "",
+            "engine": "migra",
+            "target_fingerprint": fingerprint,
+            "script": script,
+        });
+        std::fs::write(&plan_path, plan.to_string()).unwrap();
+    };
+    let args = ApplyArgs {
+        plan: plan_path.to_str().unwrap().to_string(),
+        allow_destructive: false,
+        lock: LockOptions::default(),
+    };
+
+    write_plan(&fingerprint, "create table planned (id int);");
+    let err = postgit::apply_plan(&args, &config).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("The schema of the target database changed since"));
+
+    write_plan(&changed_fingerprint, "create table planned (id int);");
+    postgit::apply_plan(&args, &config).unwrap();
+    let rows = execute_statement(&target_config, "select to_regclass('planned')::text;");
+    assert_eq!(Some("planned"), rows[0].get::<_, Option<&str>>(0));
+
+    let deployments = postgit::db::get_deployments(&target_config, 10, None).unwrap();
+    assert_eq!("create table planned (id int);", deployments[0].script);
+}
+
+#[test]
+fn it_refuses_destructive_changes_unless_allowed() {
+    let mut config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    execute_statement(&target_config, "create table legacy_orders (id int);");
+    execute_statement(&target_config, "create table orders (id int);");
+
+    let dir = tempdir().unwrap();
+    let plan_path = dir.path().join("plan.json");
+    let plan = serde_json::json!({
+        "version": 1,
+        "from_ref": null,
+        "to_ref": "main",
+        "from_commit": null,
+        "to_commit": "0123456789abcdef",
+        "schema_path": "schema",
+        "schema_hash": "",
+        "engine": "migra",
+        "target_fingerprint": postgit::db::schema_fingerprint(&target_config).unwrap(),
+        "script": "drop table legacy_orders;\ndrop table orders;",
+    });
+    std::fs::write(&plan_path, plan.to_string()).unwrap();
+    let args = ApplyArgs {
+        plan: plan_path.to_str().unwrap().to_string(),
+        allow_destructive: false,
+        lock: LockOptions::default(),
+    };
+
+    config.push.allow_destructive = vec!["public.legacy_*".to_string()];
+    let err = postgit::apply_plan(&args, &config).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("1 destructive change(s) were not applied"));
+
+    config.push.allow_destructive = vec!["public.*".to_string()];
+    postgit::apply_plan(&args, &config).unwrap();
+    let rows = execute_statement(&target_config, "select to_regclass('orders')::text;");
+    assert_eq!(None, rows[0].get::<_, Option<&str>>(0));
+}
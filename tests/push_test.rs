@@ -1,6 +1,12 @@
 mod common;
 pub use common::*;
-use postgit::DiffArgs;
+use postgit::config::{PushConfig, RetryConfig, TransactionMode, VerifyMode};
+use postgit::db::DryRunOutcome;
+use postgit::{DiffArgs, PushOptions};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokio_postgres::NoTls;
 
 #[test]
 fn it_pushes_initial_commit() {
@@ -17,7 +23,7 @@ fn it_pushes_initial_commit() {
     postgit::db::drop_db(&target_config).unwrap();
     postgit::db::create_db(&target_config).unwrap();
 
-    postgit::apply_diff(&args, &config).unwrap();
+    postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap();
 
     let rows = execute_statement(&target_config,
             "select column_name from information_schema.columns where table_schema = 'my_app' and table_name = 'user';"
@@ -41,7 +47,7 @@ fn it_pushes_subsequent_commits() {
     postgit::db::drop_db(&target_config).unwrap();
     postgit::db::create_db(&target_config).unwrap();
 
-    postgit::apply_diff(&args, &config).unwrap();
+    postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap();
 
     let args = DiffArgs {
         from: Some(repo.commits[0].to_owned()),
@@ -51,7 +57,7 @@ fn it_pushes_subsequent_commits() {
         source_path: None,
     };
 
-    postgit::apply_diff(&args, &config).unwrap();
+    postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap();
 
     let rows = execute_statement(&target_config,
             "select column_name from information_schema.columns where table_schema = 'my_app' and table_name = 'user';"
@@ -59,3 +65,511 @@ fn it_pushes_subsequent_commits() {
 
     assert_eq!(4, rows.len());
 }
+
+#[test]
+fn it_records_deployments() {
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+
+    let deployment = postgit::db::Deployment {
+        from_commit: None,
+        to_commit: "0123456789abcdef",
+        schema_path: "schema",
+        script: "create schema my_app;",
+        down_script: Some("drop schema my_app;"),
+        engine: "migra",
+        deployed_by: Some("alice"),
+        started_at: std::time::SystemTime::now(),
+    };
+    postgit::db::deploy(&deployment, &PushConfig::default(), &target_config).unwrap();
+    postgit::db::deploy(
+        &postgit::db::Deployment {
+            from_commit: Some("0123456789abcdef"),
+            to_commit: "fedcba9876543210",
+            script: "create table my_app.t (id int);",
+            deployed_by: None,
+            ..deployment
+        },
+        &PushConfig::default(),
+        &target_config,
+    )
+    .unwrap();
+
+    let deployments = postgit::db::get_deployments(&target_config, 10, None).unwrap();
+    assert_eq!(2, deployments.len());
+    assert_eq!(
+        Some("0123456789abcdef"),
+        deployments[0].from_commit.as_deref()
+    );
+    assert_eq!("postgres", deployments[0].deployed_by);
+    assert_eq!("alice", deployments[1].deployed_by);
+    assert_eq!("create schema my_app;", deployments[1].script);
+    assert_eq!(64, deployments[1].script_hash.len());
+
+    let history = postgit::history(
+        &postgit::HistoryArgs {
+            limit: 20,
+            show: None,
+        },
+        &config,
+    )
+    .unwrap();
+    assert_eq!(
+        format!(
+            "#2 {} 0123456..fedcba9 schema by postgres with migra\n#1 {} (empty)..0123456 schema by alice with migra",
+            deployments[0].finished_at.as_deref().unwrap(),
+            deployments[1].finished_at.as_deref().unwrap()
+        ),
+        history
+    );
+}
+
+#[test]
+fn it_records_unfinished_deployments() {
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+
+    let push = PushConfig {
+        transaction_mode: TransactionMode::PerStatement,
+        ..PushConfig::default()
+    };
+    let deployment = postgit::db::Deployment {
+        from_commit: None,
+        to_commit: "0123456789abcdef",
+        schema_path: "schema",
+        script: "create table a (id int);\ncreate table b (id int);\nselect 1 / 0;",
+        down_script: None,
+        engine: "migra",
+        deployed_by: None,
+        started_at: std::time::SystemTime::now(),
+    };
+    let err = postgit::db::deploy(&deployment, &push, &target_config).unwrap_err();
+    assert_eq!(
+        "ERROR: division by zero\n2 statement(s) of the script were committed before the error",
+        err.to_string()
+    );
+
+    let deployments = postgit::db::get_deployments(&target_config, 10, None).unwrap();
+    assert_eq!(1, deployments.len());
+    assert_eq!(None, deployments[0].finished_at);
+    assert_eq!(Some(2), deployments[0].committed_statements);
+
+    let history = postgit::history(
+        &postgit::HistoryArgs {
+            limit: 20,
+            show: None,
+        },
+        &config,
+    )
+    .unwrap();
+    assert!(history.ends_with("(unfinished, 2 statement(s) committed)"));
+
+    // a failure before any statement committed is not recorded
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    let err = postgit::db::deploy(
+        &postgit::db::Deployment {
+            script: "create index concurrently i on missing (id);",
+            ..deployment
+        },
+        &push,
+        &target_config,
+    )
+    .unwrap_err();
+    assert_eq!(
+        r#"ERROR: relation "missing" does not exist"#,
+        err.to_string()
+    );
+    assert!(postgit::db::get_deployments(&target_config, 10, None)
+        .unwrap()
+        .is_empty());
+    assert!(postgit::db::is_empty(&target_config).unwrap());
+}
+
+#[test]
+fn it_refuses_to_infer_the_source_of_non_empty_databases_without_history() {
+    let repo = setup();
+    let config = get_config();
+    let args = DiffArgs {
+        from: None,
+        to: repo.commits[1].to_owned(),
+        path: String::from("schema.sql"),
+        repo_path: repo.repo_path.to_owned(),
+        source_path: None,
+    };
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    assert!(postgit::db::is_empty(&target_config).unwrap());
+    execute_statement(&target_config, "drop schema public;");
+    assert!(postgit::db::is_empty(&target_config).unwrap());
+    execute_statement(&target_config, "create schema public;");
+
+    execute_statement(&target_config, "create table existing (id int);");
+    assert!(!postgit::db::is_empty(&target_config).unwrap());
+    // deployments of other schemas are not the history of this one
+    postgit::db::deploy(
+        &postgit::db::Deployment {
+            from_commit: None,
+            to_commit: &repo.commits[1],
+            schema_path: "other.sql",
+            script: "create table other (id int);",
+            down_script: None,
+            engine: "migra",
+            deployed_by: None,
+            started_at: std::time::SystemTime::now(),
+        },
+        &PushConfig::default(),
+        &target_config,
+    )
+    .unwrap();
+
+    let err = postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap_err();
+    assert_eq!(
+        "The target database has no deployment history of schema.sql but is not empty. Use --from <REV> to specify the revision it is at, or --from empty to recreate everything",
+        err.to_string()
+    );
+}
+
+#[test]
+fn it_aborts_when_the_target_drifted() {
+    let repo = setup();
+    let config = get_config();
+    let args = DiffArgs {
+        from: Some("empty".to_string()),
+        to: repo.commits[0].to_owned(),
+        path: String::from("schema.sql"),
+        repo_path: repo.repo_path.to_owned(),
+        source_path: None,
+    };
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+
+    postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap();
+    execute_statement(&target_config, "create table my_app.hotfix (id int);");
+
+    let args = DiffArgs {
+        from: Some(repo.commits[0].to_owned()),
+        to: repo.commits[1].to_owned(),
+        ..args
+    };
+    let err = postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap_err();
+    assert!(err.to_string().contains("has drifted"));
+    assert!(err.to_string().contains("hotfix"));
+
+    let options = PushOptions {
+        drift_report_only: true,
+        ..PushOptions::default()
+    };
+    postgit::apply_diff(&args, &options, &config).unwrap();
+}
+
+#[test]
+fn it_pushes_from_a_revision_to_databases_without_history() {
+    let repo = setup();
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    // a database created before postgit was used
+    let schema = postgit::SchemaRepository::open(&repo.repo_path)
+        .unwrap()
+        .schema_files(&repo.commits[0], "schema.sql")
+        .unwrap();
+    postgit::db::run_sql_script(&schema.scripts["schema.sql"], &target_config).unwrap();
+
+    let args = DiffArgs {
+        from: Some(repo.commits[0].to_owned()),
+        to: repo.commits[1].to_owned(),
+        path: String::from("schema.sql"),
+        repo_path: repo.repo_path.to_owned(),
+        source_path: None,
+    };
+    postgit::apply_diff(&args, &PushOptions::default(), &config).unwrap();
+
+    let deployments = postgit::db::get_deployments(&target_config, 10, None).unwrap();
+    assert_eq!(1, deployments.len());
+    assert_eq!(
+        Some(repo.commits[0].as_str()),
+        deployments[0].from_commit.as_deref()
+    );
+}
+
+#[test]
+fn it_rolls_back_dry_runs() {
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+
+    let results = postgit::db::dry_run(
+        &[
+            "create table t (id int);",
+            "-- values\ninsert into t values (1), (2);",
+            "create index concurrently t_id on t (id);",
+            "insert into missing values (1);",
+            "drop table t;",
+        ],
+        &target_config,
+    )
+    .unwrap();
+
+    let outcomes: Vec<_> = results.iter().map(|r| &r.outcome).collect();
+    assert_eq!(
+        vec![
+            &DryRunOutcome::Applied(0),
+            &DryRunOutcome::Applied(2),
+            &DryRunOutcome::NotTransactional,
+            &DryRunOutcome::Failed("relation \"missing\" does not exist".to_string()),
+            &DryRunOutcome::NotRun,
+        ],
+        outcomes
+    );
+    assert_eq!("insert into t values (1), (2);", results[1].statement);
+
+    let rows = execute_statement(&target_config, "select to_regclass('t')::text;");
+    assert_eq!(None, rows[0].get::<_, Option<String>>(0));
+}
+
+#[test]
+fn it_reads_table_stats() {
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    execute_statement(&target_config, "create schema app;");
+    execute_statement(
+        &target_config,
+        "create table app.user as select generate_series(1, 1000) as id;",
+    );
+    execute_statement(&target_config, "analyze app.user;");
+
+    let stats = postgit::db::table_stats(
+        &target_config,
+        &["app.user".to_string(), "app.missing".to_string()],
+    )
+    .unwrap();
+    assert_eq!(1, stats.len());
+    assert_eq!(1000, stats["app.user"].rows);
+    assert!(stats["app.user"].size > 0);
+}
+
+/// Locks the table in a transaction which is committed after the given duration
+#[tokio::main]
+async fn hold_lock(
+    config: &tokio_postgres::Config,
+    table: &str,
+    duration: Duration,
+    locked: mpsc::Sender<()>,
+) {
+    let (client, connection) = config.connect(NoTls).await.unwrap();
+    tokio::spawn(connection);
+
+    client
+        .batch_execute(&format!("begin; lock table {};", table))
+        .await
+        .unwrap();
+    locked.send(()).unwrap();
+    tokio::time::sleep(duration).await;
+    client.batch_execute("commit;").await.unwrap();
+}
+
+fn lock_in_background(
+    config: &tokio_postgres::Config,
+    table: &'static str,
+    duration: Duration,
+) -> thread::JoinHandle<()> {
+    let config = config.clone();
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || hold_lock(&config, table, duration, sender));
+    receiver.recv().unwrap();
+    handle
+}
+
+#[test]
+fn it_retries_statements_on_lock_timeouts() {
+    let mut config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    execute_statement(&target_config, "create table t (id int);");
+
+    config.push = PushConfig {
+        lock_timeout: Some("100ms".to_string()),
+        retry: RetryConfig {
+            attempts: 5,
+            delay_ms: 200,
+            max_delay_ms: 1000,
+        },
+        ..PushConfig::default()
+    };
+    let session_config = config.target_session_config();
+    let deployment = postgit::db::Deployment {
+        from_commit: None,
+        to_commit: "0123456789abcdef",
+        schema_path: "schema",
+        script: "alter table t add column name text;",
+        down_script: None,
+        engine: "migra",
+        deployed_by: None,
+        started_at: std::time::SystemTime::now(),
+    };
+
+    let lock = lock_in_background(&target_config, "t", Duration::from_millis(800));
+    postgit::db::deploy(&deployment, &config.push, &session_config).unwrap();
+    lock.join().unwrap();
+    let rows = execute_statement(
+        &target_config,
+        "select column_name::text from information_schema.columns where table_name = 't';",
+    );
+    assert_eq!(2, rows.len());
+
+    config.push.retry.attempts = 2;
+    let lock = lock_in_background(&target_config, "t", Duration::from_millis(1500));
+    let err = postgit::db::deploy(
+        &postgit::db::Deployment {
+            script: "alter table t add column age int;",
+            ..deployment
+        },
+        &config.push,
+        &session_config,
+    )
+    .unwrap_err();
+    lock.join().unwrap();
+    assert_eq!(
+        "ERROR: canceling statement due to lock timeout\nGave up after 2 attempts to acquire the locks",
+        err.to_string()
+    );
+    assert_eq!(
+        1,
+        postgit::db::get_deployments(&target_config, 10, None)
+            .unwrap()
+            .len()
+    );
+}
+
+#[test]
+fn it_prevents_concurrent_pushes() {
+    let mut config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+
+    let key = postgit::db::push_lock_key(&config.target.database_id(), "schema");
+    assert_ne!(
+        key,
+        postgit::db::push_lock_key(&config.target.database_id(), "other")
+    );
+    let lock = postgit::db::lock_push(key, false, &target_config).unwrap();
+
+    let err = postgit::db::lock_push(key, false, &target_config)
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.starts_with(
+        "Another push of the schema to the target database is in progress: the lock is held by pid "
+    ));
+    assert!(err.contains("(user postgres, application postgit, client "));
+    assert!(err.ends_with("Use --wait to wait for it"));
+    postgit::db::lock_push(
+        postgit::db::push_lock_key("other", "schema"),
+        false,
+        &target_config,
+    )
+    .unwrap();
+
+    config.push.lock_timeout = Some("100ms".to_string());
+    let err = postgit::db::lock_push(key, true, &config.target_session_config())
+        .err()
+        .unwrap();
+    assert!(err
+        .to_string()
+        .ends_with("Gave up waiting for it after lock_timeout"));
+
+    drop(lock);
+    postgit::db::lock_push(key, true, &config.target_session_config()).unwrap();
+}
+
+#[test]
+fn it_rolls_back_the_last_deployment() {
+    let repo = setup();
+    let mut config = get_config();
+    config.push.verify = VerifyMode::Off;
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    let args = postgit::RollbackArgs {
+        repo_path: repo.repo_path.to_owned(),
+        path: "./schema.sql".to_string(),
+        options: PushOptions {
+            ignore_drift: true,
+            ..PushOptions::default()
+        },
+    };
+
+    let initial = postgit::db::Deployment {
+        from_commit: None,
+        to_commit: &repo.commits[0],
+        schema_path: "schema.sql",
+        script: "create schema my_app; create table my_app.user (id int, email text);",
+        down_script: None,
+        engine: "migra",
+        deployed_by: None,
+        started_at: std::time::SystemTime::now(),
+    };
+    assert_eq!(
+        "The target database has no deployment of schema.sql to roll back",
+        postgit::rollback(&args, &config).unwrap_err().to_string()
+    );
+    postgit::db::deploy(&initial, &PushConfig::default(), &target_config).unwrap();
+    assert_eq!(
+        "Deployment #1 was recorded without a down migration, and cannot be rolled back",
+        postgit::rollback(&args, &config).unwrap_err().to_string()
+    );
+
+    postgit::db::deploy(
+        &postgit::db::Deployment {
+            from_commit: Some(&repo.commits[0]),
+            to_commit: &repo.commits[1],
+            script: r#"alter table "my_app"."user" alter column "email" set not null;"#,
+            down_script: Some(r#"alter table "my_app"."user" alter column "email" drop not null;"#),
+            ..initial
+        },
+        &PushConfig::default(),
+        &target_config,
+    )
+    .unwrap();
+    // a later deployment of another schema
+    postgit::db::deploy(
+        &postgit::db::Deployment {
+            schema_path: "other.sql",
+            script: "select 1;",
+            ..initial
+        },
+        &PushConfig::default(),
+        &target_config,
+    )
+    .unwrap();
+    postgit::rollback(&args, &config).unwrap();
+
+    let rows = execute_statement(
+        &target_config,
+        "select is_nullable::text from information_schema.columns where table_name = 'user' and column_name = 'email';",
+    );
+    assert_eq!("YES", rows[0].get::<_, String>(0));
+    let deployments = postgit::db::get_deployments(&target_config, 1, None).unwrap();
+    assert_eq!(
+        Some(repo.commits[1].as_str()),
+        deployments[0].from_commit.as_deref()
+    );
+    assert_eq!(repo.commits[0], deployments[0].to_commit);
+    assert_eq!(
+        Some(r#"alter table "my_app"."user" alter column "email" set not null;"#),
+        deployments[0].down_script.as_deref()
+    );
+}
@@ -0,0 +1,266 @@
+use postgit::SchemaRepository;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+mod common;
+pub use common::*;
+
+fn sorted_paths(files: &postgit::SchemaFiles) -> Vec<&str> {
+    let mut paths: Vec<&str> = files.scripts.keys().map(String::as_str).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn it_reads_schema_subtrees() {
+    let repo = setup();
+    let schema_repo = SchemaRepository::open(&repo.repo_path).unwrap();
+
+    let directory = schema_repo
+        .schema_files(&repo.commits[2], "./schema")
+        .unwrap();
+    assert_eq!(
+        vec!["schema/schema.sql", "schema/user.sql"],
+        sorted_paths(&directory)
+    );
+    assert_eq!(
+        "create schema my_app;",
+        directory.scripts["schema/schema.sql"]
+    );
+
+    let file = schema_repo
+        .schema_files(&repo.commits[1], "schema.sql")
+        .unwrap();
+    assert_eq!(vec!["schema.sql"], sorted_paths(&file));
+
+    let missing = schema_repo
+        .schema_files(&repo.commits[0], "schema")
+        .unwrap();
+    assert!(missing.scripts.is_empty());
+}
+
+fn git(repo_path: &str, args: &[&str]) {
+    Command::new("git")
+        .args(args)
+        .current_dir(repo_path)
+        .output()
+        .unwrap();
+}
+
+#[test]
+fn it_resolves_merge_bases() {
+    let repo = setup();
+    git(
+        &repo.repo_path,
+        &["checkout", "-b", "feature", &repo.commits[1]],
+    );
+    fs::write(
+        format!("{}/schema.sql", repo.repo_path),
+        "create schema my_feature;",
+    )
+    .unwrap();
+    let feature_commit = commit_all(&repo.repo_path);
+    let schema_repo = SchemaRepository::open(&repo.repo_path).unwrap();
+
+    let base = schema_repo
+        .schema_files(
+            &format!("{}...{}", repo.commits[2], feature_commit),
+            "schema.sql",
+        )
+        .unwrap();
+    assert!(base.scripts["schema.sql"].contains("email text not null"));
+
+    let base_from_head = schema_repo
+        .schema_files(&format!("{}...", repo.commits[2]), "schema.sql")
+        .unwrap();
+    assert_eq!(base.scripts, base_from_head.scripts);
+}
+
+#[test]
+fn it_peels_tags_to_commits() {
+    let repo = setup();
+    git(
+        &repo.repo_path,
+        &["tag", "-a", "v1", "-m", "v1", &repo.commits[1]],
+    );
+    git(
+        &repo.repo_path,
+        &["tag", "-a", "tree", "-m", "tree", "HEAD^{tree}"],
+    );
+    let schema_repo = SchemaRepository::open(&repo.repo_path).unwrap();
+
+    let tagged = schema_repo.schema_files("v1", "schema.sql").unwrap();
+    assert!(tagged.scripts["schema.sql"].contains("email text not null"));
+
+    let err = schema_repo.schema_files("tree", "schema").unwrap_err();
+    assert_eq!(
+        "tree is a tag pointing to a tree, not a commit",
+        err.to_string()
+    );
+
+    let err = schema_repo
+        .schema_files("HEAD^{tree}", "schema")
+        .unwrap_err();
+    assert_eq!(
+        "HEAD^{tree} resolves to a tree, not a commit. Use a branch, a tag or a commit id",
+        err.to_string()
+    );
+}
+
+#[test]
+fn it_detects_renames() {
+    let repo = setup();
+    let path = |p: &str| format!("{}/{}", repo.repo_path, p);
+    fs::create_dir(path("db")).unwrap();
+    fs::rename(path("schema"), path("db/schema")).unwrap();
+    fs::rename(path("db/schema/schema.sql"), path("db/schema/my_app.sql")).unwrap();
+    let renamed_commit = commit_all(&repo.repo_path);
+    let schema_repo = SchemaRepository::open(&repo.repo_path).unwrap();
+
+    let rename = schema_repo
+        .detect_rename(&repo.commits[2], &renamed_commit, "db/schema")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!("schema", rename.source_path);
+    assert_eq!(
+        vec![
+            (
+                "schema/schema.sql".to_string(),
+                "db/schema/my_app.sql".to_string()
+            ),
+            (
+                "schema/user.sql".to_string(),
+                "db/schema/user.sql".to_string()
+            ),
+        ],
+        rename.files
+    );
+    assert_eq!(
+        "Detected rename: schema -> db/schema\nDetected rename: schema/schema.sql -> db/schema/my_app.sql\n",
+        rename.to_string()
+    );
+
+    assert_eq!(
+        None,
+        schema_repo
+            .detect_rename(&repo.commits[2], &renamed_commit, "other")
+            .unwrap()
+    );
+
+    // a new schema, with a file resembling a deleted one
+    fs::remove_dir_all(path("db")).unwrap();
+    fs::create_dir(path("reports")).unwrap();
+    fs::write(path("reports/schema.sql"), "create schema reports;").unwrap();
+    fs::write(
+        path("reports/views.sql"),
+        "create view reports.v as select 1;",
+    )
+    .unwrap();
+    fs::write(
+        path("reports/user.sql"),
+        r#"
+          -- import schema/schema.sql
+          create table reports.user (
+            id int primary key generated always as identity,
+            email text not null
+          );"#,
+    )
+    .unwrap();
+    let new_schema_commit = commit_all(&repo.repo_path);
+    assert_eq!(
+        None,
+        schema_repo
+            .detect_rename(&repo.commits[2], &new_schema_commit, "reports")
+            .unwrap()
+    );
+}
+
+#[test]
+fn it_reads_bare_repositories_and_worktrees() {
+    let repo = setup();
+    let dir = tempdir().unwrap();
+    let bare_path = dir.path().join("repo.git").to_str().unwrap().to_string();
+    git(
+        &repo.repo_path,
+        &["clone", "--bare", &repo.repo_path, &bare_path],
+    );
+    let worktree_path = dir.path().join("worktree").to_str().unwrap().to_string();
+    git(
+        &repo.repo_path,
+        &["worktree", "add", &worktree_path, &repo.commits[1]],
+    );
+
+    for repo_path in [&bare_path, &worktree_path] {
+        let schema_repo = SchemaRepository::open(repo_path).unwrap();
+        let files = schema_repo
+            .schema_files(&repo.commits[2], "schema")
+            .unwrap();
+        assert_eq!(
+            vec!["schema/schema.sql", "schema/user.sql"],
+            sorted_paths(&files)
+        );
+    }
+
+    let worktree_head = SchemaRepository::open(&worktree_path)
+        .unwrap()
+        .schema_files("HEAD", "schema.sql")
+        .unwrap();
+    assert_eq!(vec!["schema.sql"], sorted_paths(&worktree_head));
+}
+
+#[test]
+fn it_reads_submodules() {
+    let repo = setup();
+    let shared = setup();
+    git(
+        &repo.repo_path,
+        &[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            &shared.repo_path,
+            "vendor/shared",
+        ],
+    );
+    commit_all(&repo.repo_path);
+    let dir = tempdir().unwrap();
+    let bare_path = dir.path().join("repo.git").to_str().unwrap().to_string();
+    git(
+        &repo.repo_path,
+        &["clone", "--bare", &repo.repo_path, &bare_path],
+    );
+
+    for repo_path in [&repo.repo_path, &bare_path] {
+        let schema_repo = SchemaRepository::open(repo_path).unwrap();
+
+        let files = schema_repo.schema_files("HEAD", "").unwrap();
+        assert_eq!(
+            vec![
+                ".gitmodules",
+                "schema/schema.sql",
+                "schema/user.sql",
+                "vendor/shared/schema/schema.sql",
+                "vendor/shared/schema/user.sql"
+            ],
+            sorted_paths(&files)
+        );
+
+        let files = schema_repo
+            .schema_files("HEAD", "vendor/shared/schema")
+            .unwrap();
+        assert_eq!(
+            vec![
+                "vendor/shared/schema/schema.sql",
+                "vendor/shared/schema/user.sql"
+            ],
+            sorted_paths(&files)
+        );
+        assert_eq!(
+            "create schema my_app;",
+            files.scripts["vendor/shared/schema/schema.sql"]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+pub use common::*;
+use postgit::{SchemaArgs, StatusArgs, StatusFormat};
+
+#[test]
+fn it_reports_the_drift_of_databases_postgit_never_pushed_to() {
+    let repo = setup();
+    let config = get_config();
+    let target_config = config.target.to_tokio_postgres_config();
+    postgit::db::drop_db(&target_config).unwrap();
+    postgit::db::create_db(&target_config).unwrap();
+    let schema = postgit::SchemaRepository::open(&repo.repo_path)
+        .unwrap()
+        .schema_files(&repo.commits[1], "schema.sql")
+        .unwrap();
+    postgit::db::run_sql_script(&schema.scripts["schema.sql"], &target_config).unwrap();
+
+    let args = StatusArgs {
+        schema: SchemaArgs {
+            repo_path: repo.repo_path.to_owned(),
+            git_ref: Some(repo.commits[1].to_owned()),
+            path: String::from("schema.sql"),
+        },
+        format: StatusFormat::Text,
+    };
+    let status = postgit::status(&args, &config).unwrap();
+    assert!(!status.has_drift(), "{}", status.statements);
+
+    execute_statement(&target_config, "create table my_app.hotfix (id int);");
+    let status = postgit::status(&args, &config).unwrap();
+    assert!(status.has_drift());
+    assert!(status.statements.contains("hotfix"));
+}